@@ -40,7 +40,7 @@ impl Album {
         "#,
             params![id],
         )
-        .get(0)
+        .first()
         .cloned()
     }
     pub fn by_title(db: &DB, title: &str) -> Vec<Album> {
@@ -55,4 +55,16 @@ impl Album {
             params![format!("%{title}%")],
         )
     }
+    pub fn by_artist_id(db: &DB, id: u32) -> Vec<Album> {
+        db.query(
+            r#"
+        SELECT al.album_id, al.artist_id, al.album_title, ar.artist_name, al.album_year, al.album_songs
+        FROM Albums al
+        JOIN Artists ar ON al.artist_id = ar.artist_id
+        WHERE al.artist_id = ?1
+        ORDER BY al.album_year, al.album_title
+        "#,
+            params![id],
+        )
+    }
 }
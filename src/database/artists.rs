@@ -0,0 +1,67 @@
+use rusqlite::params;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::{DBObject, DB};
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Artist {
+    pub artist_id: u32,
+    pub name: String,
+    pub albums: u32,
+}
+impl DBObject for Artist {
+    fn from_row(row: &rusqlite::Row) -> Result<Self, rusqlite::Error>
+    where
+        Self: Sized,
+    {
+        Ok(Artist {
+            artist_id: row.get(0)?,
+            name: row.get(1)?,
+            albums: row.get(2)?,
+        })
+    }
+}
+
+impl Artist {
+    /// Every artist with at least one album.
+    pub fn all(db: &DB) -> Vec<Artist> {
+        db.query(
+            r#"
+        SELECT ar.artist_id, ar.artist_name, COUNT(al.album_id)
+        FROM Artists ar
+        JOIN Albums al ON ar.artist_id = al.artist_id
+        GROUP BY ar.artist_id
+        ORDER BY ar.artist_name
+        "#,
+            params![],
+        )
+    }
+    pub fn by_id(db: &DB, id: u32) -> Option<Artist> {
+        db.query(
+            r#"
+        SELECT ar.artist_id, ar.artist_name, COUNT(al.album_id)
+        FROM Artists ar
+        LEFT JOIN Albums al ON ar.artist_id = al.artist_id
+        WHERE ar.artist_id = ?1
+        GROUP BY ar.artist_id
+        "#,
+            params![id],
+        )
+        .first()
+        .cloned()
+    }
+    pub fn by_name(db: &DB, name: &str) -> Vec<Artist> {
+        db.query(
+            r#"
+        SELECT ar.artist_id, ar.artist_name, COUNT(al.album_id)
+        FROM Artists ar
+        JOIN Albums al ON ar.artist_id = al.artist_id
+        WHERE ar.artist_name LIKE ?1
+        GROUP BY ar.artist_id
+        ORDER BY ar.artist_name
+        "#,
+            params![format!("%{name}%")],
+        )
+    }
+}
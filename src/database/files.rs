@@ -1,49 +1,129 @@
-use std::fs::{read_dir, File};
+use std::{
+    fs::{canonicalize, metadata, read_dir, read_to_string, File},
+    path::Path,
+    time::UNIX_EPOCH,
+};
 
-use lofty::{Accessor, Probe, TaggedFileExt};
-use rusqlite::params;
+use lofty::{Accessor, ItemKey, Probe, Tag, TaggedFileExt};
+use rusqlite::{params, Params};
 
-use crate::player::source::Source;
+use crate::player::source;
 
-use super::DB;
+use super::{formats, Format, Gain, Playlist, Song, DB};
+
+pub const MUSIC: &[&str] = &["ogg", "mp3", "m4a", "wav", "flac"];
+pub const PLAYLISTS: &[&str] = &["m3u", "m3u8", "pls", "xspf"];
 
 pub struct AudioFile {
     album_artist: String,
     album_title: String,
     album_year: u32,
     album_songs: u32,
-    album_cover: Vec<u8>,
+    album_cover: Option<Vec<u8>>,
     song_artist: String,
     song_title: String,
     song_flie: String,
     song_index: u32,
     song_ms: u32,
+    song_gain: Gain,
+    /// some of the above was guessed from the path instead of read from tags
+    song_inferred: bool,
+    song_stamp: Stamp,
 }
 
-impl AudioFile {
-    pub fn open(path: &str) -> Option<AudioFile> {
-        match Probe::open(path).unwrap().read() {
-            Ok(tag) => tag.primary_tag().and_then(|tag| {
-                if let Ok(src) = Source::new(File::open(path).unwrap()) {
-                    Some(AudioFile {
-                        // Source::new;/
-                        album_artist: tag.get_string(&lofty::ItemKey::AlbumArtist)?.into(),
-                        album_title: tag.album()?.into(),
-                        album_year: tag.year()?,
-                        album_songs: tag.track_total()?,
-                        album_cover: tag.pictures().get(0)?.data().to_owned(),
-                        song_artist: tag.artist()?.into(),
-                        song_title: tag.title()?.into(),
-                        song_flie: path.into(),
-                        song_index: tag.track()?,
-                        song_ms: src.duration().as_millis() as u32,
+/// Modification time in nanoseconds and size of a file, it is read again once either changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stamp {
+    mtime: i64,
+    size: i64,
+}
+
+impl Stamp {
+    pub fn of(path: &str) -> Option<Stamp> {
+        let meta = metadata(path).ok()?;
+        let mtime = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Stamp {
+            mtime: mtime.as_nanos() as i64,
+            size: meta.len() as i64,
+        })
+    }
+    /// The stamp stored when `path` was last read, None for files that were never read.
+    pub fn scanned(db: &DB, path: &str) -> Option<Stamp> {
+        db.conn
+            .query_row(
+                "SELECT song_mtime, song_size FROM Songs WHERE song_file = ?1",
+                params![path],
+                |row| {
+                    Ok(Stamp {
+                        mtime: row.get(0)?,
+                        size: row.get(1)?,
                     })
-                } else {
-                    None
-                }
-            }),
-            Err(_) => None,
-        }
+                },
+            )
+            .ok()
+    }
+}
+
+impl AudioFile {
+    /// Reads whatever tags the file has, filling the gaps from a "Artist/Album/01 - Title.ext" layout
+    /// below `root`. None only when the file can not be played.
+    pub fn open(path: &str, root: &str, stamp: Stamp) -> Option<AudioFile> {
+        let duration = source::duration(File::open(path).ok()?).ok()?;
+        let tagged = Probe::open(path).ok().and_then(|probe| probe.read().ok());
+        let tag = tagged
+            .as_ref()
+            .and_then(|file| file.primary_tag().or_else(|| file.first_tag()));
+        let file = Path::new(path);
+        let (file_title, file_index) = from_file_name(file);
+        let below_root = |dir: &&Path| dir.strip_prefix(root).is_ok_and(|dir| dir != Path::new(""));
+        let album_dir = file.parent().filter(below_root);
+        let artist_dir = album_dir.and_then(Path::parent).filter(below_root);
+        let mut inferred = false;
+        let song_artist = tag.and_then(|tag| tag.artist()).map(String::from);
+        let album_artist = tag
+            .and_then(|tag| tag.get_string(&ItemKey::AlbumArtist))
+            .map(String::from)
+            .or(song_artist.clone());
+        let song_artist = infer(
+            song_artist,
+            || dir_name(artist_dir, "Unknown Artist"),
+            &mut inferred,
+        );
+        let gain = |key| tag.and_then(|tag| replay_gain(tag, key));
+        Some(AudioFile {
+            album_artist: album_artist.unwrap_or(song_artist.clone()),
+            album_title: infer(
+                tag.and_then(|tag| tag.album()).map(String::from),
+                || dir_name(album_dir, "Unknown Album"),
+                &mut inferred,
+            ),
+            album_year: tag.and_then(|tag| tag.year()).unwrap_or(0),
+            album_songs: tag.and_then(|tag| tag.track_total()).unwrap_or(0),
+            album_cover: tag
+                .and_then(|tag| tag.pictures().first())
+                .map(|picture| picture.data().to_owned()),
+            song_artist,
+            song_title: infer(
+                tag.and_then(|tag| tag.title()).map(String::from),
+                || file_title,
+                &mut inferred,
+            ),
+            song_flie: path.into(),
+            song_index: infer(
+                tag.and_then(|tag| tag.track()),
+                || file_index.unwrap_or(0),
+                &mut inferred,
+            ),
+            song_ms: duration.as_millis() as u32,
+            song_gain: Gain {
+                track_gain: gain(ItemKey::ReplayGainTrackGain),
+                track_peak: gain(ItemKey::ReplayGainTrackPeak),
+                album_gain: gain(ItemKey::ReplayGainAlbumGain),
+                album_peak: gain(ItemKey::ReplayGainAlbumPeak),
+            },
+            song_inferred: inferred,
+            song_stamp: stamp,
+        })
     }
     pub fn insert(&self, db: &DB) -> Result<(), rusqlite::Error> {
         db.conn.execute(
@@ -59,9 +139,8 @@ impl AudioFile {
             WHERE a.artist_name = ?1;"#,
         )?;
         let album_artist_id: u32 =
-            artist_query.query_row(params![self.album_artist], |row| Ok(row.get(0)?))?;
-        let artist_id: u32 =
-            artist_query.query_row(params![self.song_artist], |row| Ok(row.get(0)?))?;
+            artist_query.query_row(params![self.album_artist], |row| row.get(0))?;
+        let artist_id: u32 = artist_query.query_row(params![self.song_artist], |row| row.get(0))?;
 
         db.conn.execute(
             r#"
@@ -81,9 +160,15 @@ impl AudioFile {
             FROM Albums a
             WHERE a.album_title = ?1;"#,
             params![self.album_title],
-            |row| Ok(row.get(0)?),
+            |row| row.get(0),
         )?;
 
+        if let Some(song_id) = self.moved_from(db, album_id, artist_id)? {
+            db.conn.execute(
+                "UPDATE Songs SET song_file = ?2 WHERE song_id = ?1",
+                params![song_id, self.song_flie],
+            )?;
+        }
         db.conn.execute(
             r#"
             INSERT OR IGNORE INTO Songs(song_title, album_id, artist_id, song_file, song_index, song_ms)
@@ -97,28 +182,205 @@ impl AudioFile {
                 self.song_ms
             ],
         )?;
+        db.conn.execute(
+            r#"
+            UPDATE OR IGNORE Songs
+            SET song_title = ?2, album_id = ?3, artist_id = ?4, song_index = ?5, song_ms = ?6
+            WHERE song_file = ?1"#,
+            params![
+                self.song_flie,
+                self.song_title,
+                album_id,
+                artist_id,
+                self.song_index,
+                self.song_ms
+            ],
+        )?;
+        db.conn.execute(
+            r#"
+            UPDATE Songs
+            SET song_track_gain = ?2, song_track_peak = ?3, song_album_gain = ?4, song_album_peak = ?5,
+                song_inferred = ?6, song_mtime = ?7, song_size = ?8
+            WHERE song_file = ?1"#,
+            params![
+                self.song_flie,
+                self.song_gain.track_gain,
+                self.song_gain.track_peak,
+                self.song_gain.album_gain,
+                self.song_gain.album_peak,
+                self.song_inferred,
+                self.song_stamp.mtime,
+                self.song_stamp.size
+            ],
+        )?;
         Ok(())
     }
+    /// The song whose file is gone and that has the same tags as this new file, or else the only one
+    /// with the same stamp. A moved or renamed file keeps its id this way, so queues and playlists
+    /// still find it, while files copied at once can not take each other's ids.
+    fn moved_from(
+        &self,
+        db: &DB,
+        album_id: u32,
+        artist_id: u32,
+    ) -> Result<Option<u32>, rusqlite::Error> {
+        if Stamp::scanned(db, &self.song_flie).is_some() {
+            return Ok(None);
+        }
+        let tagged = vanished(
+            db,
+            "song_title = ?1 AND album_id = ?2 AND artist_id = ?3",
+            params![self.song_title, album_id, artist_id],
+        )?;
+        if let Some(song_id) = tagged.first() {
+            return Ok(Some(*song_id));
+        }
+        let stamped = vanished(
+            db,
+            "song_mtime = ?1 AND song_size = ?2",
+            params![self.song_stamp.mtime, self.song_stamp.size],
+        )?;
+        Ok(match stamped[..] {
+            [song_id] => Some(song_id),
+            _ => None,
+        })
+    }
+}
+
+/// The songs matching `filter` whose files no longer exist.
+fn vanished(db: &DB, filter: &str, params: impl Params) -> Result<Vec<u32>, rusqlite::Error> {
+    let mut query = db.conn.prepare(&format!(
+        "SELECT song_id, song_file FROM Songs WHERE {filter}"
+    ))?;
+    let songs = query
+        .query_map(params, |row| {
+            Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(songs
+        .into_iter()
+        .filter(|(_, file)| !Path::new(file).exists())
+        .map(|(song_id, _)| song_id)
+        .collect())
+}
+
+/// The tag value if there is one, otherwise the guess, noting that something was guessed.
+fn infer<T>(tagged: Option<T>, guess: impl FnOnce() -> T, inferred: &mut bool) -> T {
+    tagged.unwrap_or_else(|| {
+        *inferred = true;
+        guess()
+    })
+}
+
+/// Title and track number from a file name like "03 - Title.flac".
+fn from_file_name(file: &Path) -> (String, Option<u32>) {
+    let stem = file
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+    let digits = stem.len() - stem.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let title = stem[digits..].trim_start_matches([' ', '-', '.', '_']);
+    let title = match title.is_empty() {
+        true => stem,
+        false => title,
+    };
+    (title.into(), stem[..digits].parse().ok())
 }
 
-pub fn get_paths(path: &str) -> Vec<String> {
+fn dir_name(dir: Option<&Path>, unknown: &str) -> String {
+    dir.and_then(|dir| dir.file_name())
+        .and_then(|name| name.to_str())
+        .unwrap_or(unknown)
+        .into()
+}
+
+/// Parses values like "-6.20 dB" or "0.988547".
+fn replay_gain(tag: &Tag, key: ItemKey) -> Option<f32> {
+    tag.get_string(&key)?
+        .trim()
+        .trim_end_matches("dB")
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Imports the playlist file at `path` as a playlist named after the file. Playlists created or edited
+/// some other way are left alone, even when they have the same name.
+pub fn import_playlist(db: &DB, path: &str) -> Result<(), rusqlite::Error> {
+    let source = path;
+    let path = Path::new(path);
+    let (Some(format), Some(name), Ok(text)) = (
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Format::from_ext),
+        path.file_stem().and_then(|name| name.to_str()),
+        read_to_string(path),
+    ) else {
+        return Ok(());
+    };
+    let dir = path.parent().unwrap_or(Path::new("/"));
+    let songs: Vec<u32> = format
+        .parse(&text)
+        .iter()
+        .map(|entry| formats::resolve(dir, entry, format))
+        .filter_map(|file| {
+            Song::by_file(db, file.to_str()?).or_else(|| {
+                let file = canonicalize(file).ok()?;
+                Song::by_file(db, file.to_str()?)
+            })
+        })
+        .map(|song| song.song_id)
+        .collect();
+    if songs.is_empty() {
+        return Ok(());
+    }
+    let playlist = match (Playlist::by_source(db, source), Playlist::by_name(db, name)) {
+        (Some(playlist), _) => playlist,
+        (None, Some(_)) => return Ok(()),
+        (None, None) => Playlist::create(db, name)?,
+    };
+    playlist.import(db, source, &songs)
+}
+
+/// Whether the extension of `path` is one of `kinds`.
+pub fn is_kind(path: &Path, kinds: &[&str]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| kinds.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Files below `path` with one of the `kinds` extensions, and whether every folder could be read.
+/// Files missing from an incomplete listing may still exist, e.g. on a drive that is not mounted.
+pub fn get_paths(path: &str, kinds: &[&str]) -> (Vec<String>, bool) {
     let mut paths = vec![];
-    read_dir(path).unwrap().for_each(|entry| {
-        if let Ok(entry) = entry {
-            let meta = entry.metadata().unwrap();
-            let path = entry.path();
-            let path = path.as_path().to_str().unwrap();
-            let is_music = path.ends_with(".ogg")
-                || path.ends_with(".mp3")
-                || path.ends_with(".m4a")
-                || path.ends_with(".wav")
-                || path.ends_with(".flac");
-            if meta.is_file() && is_music {
-                paths.push(path.to_owned())
-            } else if meta.is_dir() {
-                paths.append(&mut get_paths(path))
-            }
+    let complete = walk(path, kinds, &mut paths);
+    (paths, complete)
+}
+
+fn walk(path: &str, kinds: &[&str], paths: &mut Vec<String>) -> bool {
+    let Ok(entries) = read_dir(path) else {
+        return false;
+    };
+    let mut complete = true;
+    for entry in entries {
+        let Ok(entry) = entry else {
+            complete = false;
+            continue;
+        };
+        let Ok(meta) = entry.metadata() else {
+            complete = false;
+            continue;
+        };
+        let path = entry.path();
+        let is_kind = is_kind(&path, kinds);
+        let Some(path) = path.to_str() else {
+            continue;
+        };
+        if meta.is_file() && is_kind {
+            paths.push(path.to_owned())
+        } else if meta.is_dir() {
+            complete &= walk(path, kinds, paths)
         }
-    });
-    paths
+    }
+    complete
 }
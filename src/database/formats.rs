@@ -0,0 +1,265 @@
+use std::path::{Path, PathBuf};
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use quick_xml::{escape::escape, events::Event, Reader};
+
+use super::Song;
+
+/// Characters escaped in the `file://` URIs written to XSPF files.
+const URI: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    M3u,
+    Pls,
+    Xspf,
+}
+
+impl Format {
+    pub fn from_ext(ext: &str) -> Option<Format> {
+        match ext.to_lowercase().as_str() {
+            "m3u" | "m3u8" => Some(Format::M3u),
+            "pls" => Some(Format::Pls),
+            "xspf" => Some(Format::Xspf),
+            _ => None,
+        }
+    }
+    pub fn mime(&self) -> &'static str {
+        match self {
+            Format::M3u => "audio/x-mpegurl",
+            Format::Pls => "audio/x-scpls",
+            Format::Xspf => "application/xspf+xml",
+        }
+    }
+    /// The entries of a playlist file as written, paths or URIs.
+    pub fn parse(&self, text: &str) -> Vec<String> {
+        let text = text.trim_start_matches('\u{feff}');
+        match self {
+            Format::M3u => text
+                .lines()
+                .map(|line| line.trim())
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| line.into())
+                .collect(),
+            Format::Pls => {
+                let mut entries: Vec<(u32, String)> = text
+                    .lines()
+                    .filter_map(|line| {
+                        let (key, value) = line.trim().split_once('=')?;
+                        let number = key
+                            .trim()
+                            .to_lowercase()
+                            .strip_prefix("file")?
+                            .parse()
+                            .ok()?;
+                        Some((number, value.trim().into()))
+                    })
+                    .collect();
+                entries.sort_by_key(|(number, _)| *number);
+                entries.into_iter().map(|(_, entry)| entry).collect()
+            }
+            Format::Xspf => {
+                let mut reader = Reader::from_str(text);
+                reader.trim_text(true);
+                let mut entries = vec![];
+                let mut location = false;
+                loop {
+                    match reader.read_event() {
+                        Ok(Event::Start(tag)) => location = tag.name().as_ref() == b"location",
+                        Ok(Event::End(_)) => location = false,
+                        Ok(Event::Text(text)) if location => {
+                            if let Ok(text) = text.unescape() {
+                                entries.push(text.into_owned())
+                            }
+                        }
+                        Ok(Event::Eof) | Err(_) => break,
+                        _ => {}
+                    }
+                }
+                entries
+            }
+        }
+    }
+    pub fn export(&self, name: &str, songs: &[Song]) -> String {
+        match self {
+            Format::M3u => {
+                let mut out = String::from("#EXTM3U\n");
+                for song in songs {
+                    out += &format!(
+                        "#EXTINF:{},{} - {}\n{}\n",
+                        song.ms / 1_000,
+                        song.artist,
+                        song.title,
+                        song.file
+                    );
+                }
+                out
+            }
+            Format::Pls => {
+                let mut out = String::from("[playlist]\n");
+                for (i, song) in songs.iter().enumerate() {
+                    let n = i + 1;
+                    out += &format!(
+                        "File{n}={}\nTitle{n}={} - {}\nLength{n}={}\n",
+                        song.file,
+                        song.artist,
+                        song.title,
+                        song.ms / 1_000
+                    );
+                }
+                out + &format!("NumberOfEntries={}\nVersion=2\n", songs.len())
+            }
+            Format::Xspf => {
+                let mut out = String::from(
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n",
+                );
+                out += &format!("  <title>{}</title>\n  <trackList>\n", escape(name));
+                for song in songs {
+                    out += &format!(
+                        "    <track>\n      <location>file://{}</location>\n      <title>{}</title>\n      <creator>{}</creator>\n      <album>{}</album>\n      <duration>{}</duration>\n    </track>\n",
+                        escape(&utf8_percent_encode(&song.file, URI).to_string()),
+                        escape(&song.title),
+                        escape(&song.artist),
+                        escape(&song.album),
+                        song.ms
+                    );
+                }
+                out + "  </trackList>\n</playlist>\n"
+            }
+        }
+    }
+}
+
+/// Turns a playlist entry into a path, relative entries are relative to the playlist's directory.
+pub fn resolve(dir: &Path, entry: &str, format: Format) -> PathBuf {
+    let uri = entry
+        .strip_prefix("file://localhost")
+        .or_else(|| entry.strip_prefix("file://"));
+    let entry = match uri {
+        Some(uri) => percent_decode_str(uri).decode_utf8_lossy().into_owned(),
+        None if format == Format::Xspf => {
+            percent_decode_str(entry).decode_utf8_lossy().into_owned()
+        }
+        None => entry.into(),
+    };
+    match Path::new(&entry).is_absolute() {
+        true => PathBuf::from(entry),
+        false => dir.join(entry),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{resolve, Format};
+    use crate::database::{songs::Gain, Song};
+
+    fn song(file: &str, title: &str) -> Song {
+        Song {
+            song_id: 1,
+            artist_id: 1,
+            album_id: 1,
+            title: title.into(),
+            artist: "Artist & Co".into(),
+            album: "Album".into(),
+            ms: 61_500,
+            inferred: false,
+            file: file.into(),
+            size: 0,
+            gain: Gain::default(),
+        }
+    }
+
+    #[test]
+    fn parses_m3u() {
+        let text = "\u{feff}#EXTM3U\n#EXTINF:61,A - B\n  01 - One.flac  \r\n\n/music/Two.flac\nfile:///music/Three%20Four.flac\n";
+        assert_eq!(
+            Format::M3u.parse(text),
+            [
+                "01 - One.flac",
+                "/music/Two.flac",
+                "file:///music/Three%20Four.flac"
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_pls_in_file_order() {
+        let text = "[playlist]\nFile10=ten.flac\nTitle10=Ten\nfile2 = two.flac\nFile1=one.flac\nFilex=bad.flac\nNumberOfEntries=3\n";
+        assert_eq!(
+            Format::Pls.parse(text),
+            ["one.flac", "two.flac", "ten.flac"]
+        );
+    }
+
+    #[test]
+    fn resolves_entries() {
+        let dir = Path::new("/music/lists");
+        let resolved = |entry, format| resolve(dir, entry, format);
+        assert_eq!(
+            resolved("../A/01 - One.flac", Format::M3u),
+            PathBuf::from("/music/lists/../A/01 - One.flac")
+        );
+        assert_eq!(
+            resolved("/music/A/Two.flac", Format::Pls),
+            PathBuf::from("/music/A/Two.flac")
+        );
+        // only URIs are percent-decoded, a plain path may contain a literal %
+        assert_eq!(
+            resolved("100%25.flac", Format::M3u),
+            PathBuf::from("/music/lists/100%25.flac")
+        );
+        assert_eq!(
+            resolved("file:///music/A/Three%20%26%20Four.flac", Format::M3u),
+            PathBuf::from("/music/A/Three & Four.flac")
+        );
+        assert_eq!(
+            resolved("file://localhost/music/A/Five.flac", Format::Pls),
+            PathBuf::from("/music/A/Five.flac")
+        );
+        assert_eq!(
+            resolved("B/Six%20Seven.flac", Format::Xspf),
+            PathBuf::from("/music/lists/B/Six Seven.flac")
+        );
+    }
+
+    #[test]
+    fn exports_and_parses_back() {
+        let songs = [
+            song("/music/A/01 - One & Two.flac", "One <&> Two"),
+            song("/music/B/100% #1?.flac", "Three"),
+        ];
+        let dir = Path::new("/elsewhere");
+        for format in [Format::M3u, Format::Pls, Format::Xspf] {
+            let text = format.export("Mix & Match", &songs);
+            let files: Vec<PathBuf> = format
+                .parse(&text)
+                .iter()
+                .map(|entry| resolve(dir, entry, format))
+                .collect();
+            assert_eq!(
+                files,
+                songs
+                    .iter()
+                    .map(|song| PathBuf::from(&song.file))
+                    .collect::<Vec<_>>(),
+                "{format:?}"
+            );
+        }
+        let xspf = Format::Xspf.export("Mix & Match", &songs);
+        assert!(xspf.contains("<title>Mix &amp; Match</title>"));
+        assert!(xspf.contains("<location>file:///music/B/100%25%20%231%3F.flac</location>"));
+        assert!(xspf.contains("<creator>Artist &amp; Co</creator>"));
+    }
+}
@@ -1,11 +1,24 @@
 mod albums;
+mod artists;
 mod covers;
 mod files;
+mod formats;
+mod playlists;
+mod presets;
 mod songs;
+mod state;
+mod watch;
 pub use albums::*;
+pub use artists::*;
 pub use covers::*;
+pub use formats::Format;
+pub use playlists::*;
+pub use presets::*;
 use rusqlite::{params, Connection, Params, Row};
 pub use songs::*;
+pub use state::*;
+use std::{path::Path, time::Duration};
+pub use watch::*;
 
 pub struct DB {
     conn: Connection,
@@ -16,16 +29,116 @@ impl DB {
         let db = DB {
             conn: Connection::open(path)?,
         };
+        // the server and the player thread each hold a connection and write at the same time
+        db.conn.busy_timeout(Duration::from_secs(5))?;
         db.init()?;
         Ok(db)
     }
-    pub fn update(&self, path: &str) -> Result<(), rusqlite::Error> {
-        for path in files::get_paths(path) {
-            if let Some(file) = files::AudioFile::open(&path) {
+    /// Brings the songs below `path` up to date, reading only files that are new or changed
+    /// and dropping the ones that are gone. `progress` is called now and then with the files done
+    /// and the total, and once more when finished. Returns false when part of `path` could not be read,
+    /// the songs that were not found there are kept.
+    pub fn update(
+        &self,
+        path: &str,
+        progress: impl Fn(usize, usize),
+    ) -> Result<bool, rusqlite::Error> {
+        let root = path;
+        let generation = self.generation()? + 1;
+        let (paths, complete) = files::get_paths(path, files::MUSIC);
+        let total = paths.len();
+        for (done, path) in paths.into_iter().enumerate() {
+            self.scan(root, &path, generation)?;
+            if done % 10 == 0 {
+                progress(done, total)
+            }
+        }
+        // an unmounted or unreadable folder is not the same as deleted files
+        if complete {
+            self.remove(root, generation)?;
+        }
+        for path in files::get_paths(path, files::PLAYLISTS).0 {
+            files::import_playlist(self, &path)?
+        }
+        progress(total, total);
+        Ok(complete)
+    }
+    /// Applies a change to `path` below the music folder `root`, which may be a file or a folder
+    /// that was added, changed or removed. Returns whether any song changed.
+    pub fn refresh(&self, root: &str, path: &str) -> Result<bool, rusqlite::Error> {
+        let generation = self.generation()?;
+        let target = Path::new(path);
+        let mut changed = false;
+        if target.is_dir() {
+            for file in files::get_paths(path, files::MUSIC).0 {
+                changed |= self.scan(root, &file, generation)?
+            }
+            for file in files::get_paths(path, files::PLAYLISTS).0 {
+                files::import_playlist(self, &file)?
+            }
+        } else if files::is_kind(target, files::MUSIC) {
+            changed |= self.scan(root, path, generation)?
+        } else if files::is_kind(target, files::PLAYLISTS) {
+            files::import_playlist(self, path)?
+        }
+        // the whole root going away means it was unmounted rather than emptied
+        if !target.exists() && Path::new(root).is_dir() {
+            changed |= self.remove(path, i64::MAX)?
+        }
+        Ok(changed)
+    }
+    /// The number of the last full scan.
+    fn generation(&self) -> Result<i64, rusqlite::Error> {
+        self.conn.query_row(
+            "SELECT COALESCE(MAX(song_generation), 0) FROM Songs",
+            params![],
+            |row| row.get(0),
+        )
+    }
+    /// Reads the music file at `path` unless it is unchanged since it was last read,
+    /// and marks it as seen by `generation`. Returns whether it was read.
+    fn scan(&self, root: &str, path: &str, generation: i64) -> Result<bool, rusqlite::Error> {
+        let Some(stamp) = files::Stamp::of(path) else {
+            return Ok(false);
+        };
+        let read = files::Stamp::scanned(self, path) != Some(stamp);
+        if read {
+            if let Some(file) = files::AudioFile::open(path, root, stamp) {
                 file.insert(self)?
             }
         }
-        Ok(())
+        self.conn.execute(
+            "UPDATE Songs SET song_generation = ?2 WHERE song_file = ?1",
+            params![path, generation],
+        )?;
+        Ok(read)
+    }
+    /// Drops the songs below `path` last seen before `generation`,
+    /// along with albums and artists left without songs. Returns whether any song was dropped.
+    /// Playlist entries of dropped songs are kept, playlists only show the songs that exist.
+    fn remove(&self, path: &str, generation: i64) -> Result<bool, rusqlite::Error> {
+        let path = path.trim_end_matches('/');
+        let tx = self.conn.unchecked_transaction()?;
+        let removed = tx.execute(
+            r#"
+            DELETE FROM Songs
+            WHERE (song_file = ?1 OR substr(song_file, 1, length(?1) + 1) = ?1 || '/')
+                AND (song_generation IS NULL OR song_generation < ?2)"#,
+            params![path, generation],
+        )?;
+        tx.execute(
+            "DELETE FROM Albums WHERE album_id NOT IN (SELECT album_id FROM Songs)",
+            params![],
+        )?;
+        tx.execute(
+            r#"
+            DELETE FROM Artists
+            WHERE artist_id NOT IN (SELECT artist_id FROM Songs)
+                AND artist_id NOT IN (SELECT artist_id FROM Albums)"#,
+            params![],
+        )?;
+        tx.commit()?;
+        Ok(removed > 0)
     }
     fn init(&self) -> Result<(), rusqlite::Error> {
         self.conn.execute(
@@ -65,6 +178,14 @@ impl DB {
                 song_index INTEGER,
                 song_ms INTEGER,
                 song_file TEXT,
+                song_track_gain REAL,
+                song_track_peak REAL,
+                song_album_gain REAL,
+                song_album_peak REAL,
+                song_inferred INTEGER,
+                song_mtime INTEGER,
+                song_size INTEGER,
+                song_generation INTEGER,
                 CONSTRAINT Songs_PK PRIMARY KEY (song_id),
                 CONSTRAINT Songs_UN UNIQUE (song_file),
                 CONSTRAINT Songs_UN UNIQUE (song_title,album_id,artist_id),
@@ -74,6 +195,102 @@ impl DB {
         "#,
             params![],
         )?;
+        self.conn.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS EqPresets (
+                preset_id INTEGER,
+                preset_name TEXT,
+                preset_eq TEXT,
+                CONSTRAINT EqPresets_PK PRIMARY KEY (preset_id),
+                CONSTRAINT EqPresets_UN UNIQUE (preset_name)
+            );
+        "#,
+            params![],
+        )?;
+        self.conn.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS EqOutputs (
+                output TEXT,
+                preset_id INTEGER,
+                CONSTRAINT EqOutputs_PK PRIMARY KEY (output),
+                CONSTRAINT EqOutputs_FK FOREIGN KEY (preset_id) REFERENCES EqPresets(preset_id)
+            );
+        "#,
+            params![],
+        )?;
+        self.conn.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS Playlists (
+                playlist_id INTEGER,
+                playlist_name TEXT,
+                playlist_source TEXT,
+                CONSTRAINT Playlists_PK PRIMARY KEY (playlist_id),
+                CONSTRAINT Playlists_UN UNIQUE (playlist_name)
+            );
+        "#,
+            params![],
+        )?;
+        self.conn.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS PlaylistEntries (
+                playlist_id INTEGER,
+                entry_pos INTEGER,
+                song_id INTEGER,
+                CONSTRAINT PlaylistEntries_PK PRIMARY KEY (playlist_id,entry_pos),
+                CONSTRAINT PlaylistEntries_FK FOREIGN KEY (playlist_id) REFERENCES Playlists(playlist_id),
+                CONSTRAINT PlaylistEntries_FK_1 FOREIGN KEY (song_id) REFERENCES Songs(song_id)
+            );
+        "#,
+            params![],
+        )?;
+        self.conn.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS QueueSongs (
+                queue_pos INTEGER,
+                song_id INTEGER,
+                CONSTRAINT QueueSongs_PK PRIMARY KEY (queue_pos),
+                CONSTRAINT QueueSongs_FK FOREIGN KEY (song_id) REFERENCES Songs(song_id)
+            );
+        "#,
+            params![],
+        )?;
+        self.conn.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS PlayerState (
+                state_id INTEGER,
+                state_index INTEGER,
+                state_order TEXT,
+                state_position INTEGER,
+                state_pause INTEGER,
+                state_mode TEXT,
+                CONSTRAINT PlayerState_PK PRIMARY KEY (state_id)
+            );
+        "#,
+            params![],
+        )?;
+        self.add_column("Songs", "song_track_gain", "REAL")?;
+        self.add_column("Songs", "song_track_peak", "REAL")?;
+        self.add_column("Songs", "song_album_gain", "REAL")?;
+        self.add_column("Songs", "song_album_peak", "REAL")?;
+        self.add_column("Songs", "song_inferred", "INTEGER")?;
+        self.add_column("Songs", "song_mtime", "INTEGER")?;
+        self.add_column("Songs", "song_size", "INTEGER")?;
+        self.add_column("Songs", "song_generation", "INTEGER")?;
+        self.add_column("Playlists", "playlist_source", "TEXT")?;
+        Ok(())
+    }
+    /// Brings tables created by older versions up to date.
+    fn add_column(&self, table: &str, column: &str, kind: &str) -> Result<(), rusqlite::Error> {
+        if self
+            .conn
+            .prepare(&format!("SELECT {column} FROM {table}"))
+            .is_err()
+        {
+            self.conn.execute(
+                &format!("ALTER TABLE {table} ADD COLUMN {column} {kind}"),
+                params![],
+            )?;
+        }
         Ok(())
     }
     fn query<T: DBObject>(&self, sql: &str, params: impl Params) -> Vec<T> {
@@ -82,10 +299,7 @@ impl DB {
             .unwrap()
             .query_map(params, |row| T::from_row(row))
             .unwrap()
-            .filter_map(|object| match object {
-                Ok(object) => Some(object),
-                Err(_) => None,
-            })
+            .filter_map(|object| object.ok())
             .collect()
     }
 }
@@ -0,0 +1,278 @@
+use rusqlite::params;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::{DBObject, Song, DB};
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Playlist {
+    pub playlist_id: u32,
+    pub name: String,
+    pub songs: u32,
+}
+impl DBObject for Playlist {
+    fn from_row(row: &rusqlite::Row) -> Result<Self, rusqlite::Error>
+    where
+        Self: Sized,
+    {
+        Ok(Playlist {
+            playlist_id: row.get(0)?,
+            name: row.get(1)?,
+            songs: row.get(2)?,
+        })
+    }
+}
+
+impl Playlist {
+    pub fn all(db: &DB) -> Vec<Playlist> {
+        db.query(
+            r#"
+        SELECT p.playlist_id, p.playlist_name, COUNT(s.song_id)
+        FROM Playlists p
+        LEFT JOIN PlaylistEntries e ON p.playlist_id = e.playlist_id
+        LEFT JOIN Songs s ON e.song_id = s.song_id
+        GROUP BY p.playlist_id
+        ORDER BY p.playlist_name
+        "#,
+            params![],
+        )
+    }
+    pub fn by_id(db: &DB, id: u32) -> Option<Playlist> {
+        db.query(
+            r#"
+        SELECT p.playlist_id, p.playlist_name, COUNT(s.song_id)
+        FROM Playlists p
+        LEFT JOIN PlaylistEntries e ON p.playlist_id = e.playlist_id
+        LEFT JOIN Songs s ON e.song_id = s.song_id
+        WHERE p.playlist_id = ?1
+        GROUP BY p.playlist_id
+        "#,
+            params![id],
+        )
+        .first()
+        .cloned()
+    }
+    pub fn by_name(db: &DB, name: &str) -> Option<Playlist> {
+        db.query(
+            r#"
+        SELECT p.playlist_id, p.playlist_name, COUNT(s.song_id)
+        FROM Playlists p
+        LEFT JOIN PlaylistEntries e ON p.playlist_id = e.playlist_id
+        LEFT JOIN Songs s ON e.song_id = s.song_id
+        WHERE p.playlist_name = ?1
+        GROUP BY p.playlist_id
+        "#,
+            params![name],
+        )
+        .first()
+        .cloned()
+    }
+    /// The playlist imported from the playlist file at `path`, unless it was edited since.
+    pub fn by_source(db: &DB, path: &str) -> Option<Playlist> {
+        db.query(
+            r#"
+        SELECT p.playlist_id, p.playlist_name, COUNT(s.song_id)
+        FROM Playlists p
+        LEFT JOIN PlaylistEntries e ON p.playlist_id = e.playlist_id
+        LEFT JOIN Songs s ON e.song_id = s.song_id
+        WHERE p.playlist_source = ?1
+        GROUP BY p.playlist_id
+        "#,
+            params![path],
+        )
+        .first()
+        .cloned()
+    }
+    /// Fails if a playlist with the same name exists.
+    pub fn create(db: &DB, name: &str) -> Result<Playlist, rusqlite::Error> {
+        db.conn.execute(
+            "INSERT INTO Playlists (playlist_name) VALUES (?1)",
+            params![name],
+        )?;
+        Playlist::by_id(db, db.conn.last_insert_rowid() as u32)
+            .ok_or(rusqlite::Error::QueryReturnedNoRows)
+    }
+    pub fn rename(&self, db: &DB, name: &str) -> Result<(), rusqlite::Error> {
+        db.conn.execute(
+            "UPDATE Playlists SET playlist_name = ?1 WHERE playlist_id = ?2",
+            params![name, self.playlist_id],
+        )?;
+        Ok(())
+    }
+    pub fn delete(&self, db: &DB) -> Result<(), rusqlite::Error> {
+        db.conn.execute(
+            "DELETE FROM PlaylistEntries WHERE playlist_id = ?1",
+            params![self.playlist_id],
+        )?;
+        db.conn.execute(
+            "DELETE FROM Playlists WHERE playlist_id = ?1",
+            params![self.playlist_id],
+        )?;
+        Ok(())
+    }
+    pub fn songs(&self, db: &DB) -> Vec<Song> {
+        db.query(
+            r#"
+        SELECT s.song_id, s.artist_id, s.album_id, s.song_title, ar.artist_name, al.album_title, s.song_ms, s.song_file,
+            s.song_track_gain, s.song_track_peak, s.song_album_gain, s.song_album_peak, s.song_inferred,
+            s.song_size
+        FROM PlaylistEntries e
+        JOIN Songs s ON e.song_id = s.song_id
+        JOIN Artists ar ON s.artist_id = ar.artist_id
+        JOIN Albums al ON s.album_id = al.album_id
+        WHERE e.playlist_id = ?1
+        ORDER BY e.entry_pos
+        "#,
+            params![self.playlist_id],
+        )
+    }
+    /// Every entry with whether its song exists. Edits keep the entries of missing songs,
+    /// positions count only the songs that exist, as in `songs`.
+    fn entries(&self, db: &DB) -> Result<Vec<(u32, bool)>, rusqlite::Error> {
+        let mut stmt = db.conn.prepare(
+            r#"
+        SELECT e.song_id, s.song_id IS NOT NULL
+        FROM PlaylistEntries e
+        LEFT JOIN Songs s ON e.song_id = s.song_id
+        WHERE e.playlist_id = ?1
+        ORDER BY e.entry_pos
+        "#,
+        )?;
+        let entries = stmt
+            .query_map(params![self.playlist_id], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect();
+        entries
+    }
+    /// Replaces every entry with `songs`. The playlist is no longer updated from the file it was imported from.
+    pub fn set_entries(&self, db: &DB, songs: &[u32]) -> Result<(), rusqlite::Error> {
+        let tx = db.conn.unchecked_transaction()?;
+        tx.execute(
+            "UPDATE Playlists SET playlist_source = NULL WHERE playlist_id = ?1",
+            params![self.playlist_id],
+        )?;
+        tx.execute(
+            "DELETE FROM PlaylistEntries WHERE playlist_id = ?1",
+            params![self.playlist_id],
+        )?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO PlaylistEntries (playlist_id, entry_pos, song_id) VALUES (?1, ?2, ?3)",
+            )?;
+            for (pos, id) in songs.iter().enumerate() {
+                insert.execute(params![self.playlist_id, pos, id])?;
+            }
+        }
+        tx.commit()
+    }
+    /// Replaces every entry with `songs` read from the playlist file at `path`.
+    pub fn import(&self, db: &DB, path: &str, songs: &[u32]) -> Result<(), rusqlite::Error> {
+        self.set_entries(db, songs)?;
+        db.conn.execute(
+            "UPDATE Playlists SET playlist_source = ?2 WHERE playlist_id = ?1",
+            params![self.playlist_id, path],
+        )?;
+        Ok(())
+    }
+    pub fn add(&self, db: &DB, songs: &[u32]) -> Result<(), rusqlite::Error> {
+        let mut entries = ids(&self.entries(db)?);
+        entries.extend_from_slice(songs);
+        self.set_entries(db, &entries)
+    }
+    pub fn remove(&self, db: &DB, pos: usize) -> Result<bool, rusqlite::Error> {
+        let entries = self.entries(db)?;
+        let Some(&at) = present(&entries).get(pos) else {
+            return Ok(false);
+        };
+        let mut entries = ids(&entries);
+        entries.remove(at);
+        self.set_entries(db, &entries)?;
+        Ok(true)
+    }
+    /// Moves `count` entries starting at `from` so the first of them ends up at `to`.
+    pub fn move_range(
+        &self,
+        db: &DB,
+        from: usize,
+        count: usize,
+        to: usize,
+    ) -> Result<(), rusqlite::Error> {
+        let mut entries = self.entries(db)?;
+        let visible = present(&entries);
+        if from >= visible.len() {
+            return Ok(());
+        }
+        let count = count.min(visible.len() - from);
+        let mut moved = Vec::new();
+        for &at in visible[from..from + count].iter().rev() {
+            moved.insert(0, entries.remove(at));
+        }
+        // missing songs stay where they are, the moved songs go before the `to`th song left
+        let at = present(&entries).get(to).copied().unwrap_or(entries.len());
+        entries.splice(at..at, moved);
+        self.set_entries(db, &ids(&entries))
+    }
+}
+
+fn ids(entries: &[(u32, bool)]) -> Vec<u32> {
+    entries.iter().map(|(id, _)| *id).collect()
+}
+
+/// The index in `entries` of each song that exists.
+fn present(entries: &[(u32, bool)]) -> Vec<usize> {
+    (0..entries.len()).filter(|&i| entries[i].1).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::params;
+
+    use super::{Playlist, DB};
+
+    fn add_song(db: &DB, id: u32) {
+        db.conn
+            .execute(
+                r#"
+            INSERT INTO Songs (song_id, song_title, album_id, artist_id, song_ms, song_file)
+            VALUES (?1, ?2, 1, 1, 1000, ?3)"#,
+                params![id, format!("Song {id}"), format!("/music/{id}.flac")],
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn edits_keep_entries_of_missing_songs() {
+        let db = DB::open(":memory:").unwrap();
+        db.conn
+            .execute("INSERT INTO Artists VALUES (1, 'Artist')", params![])
+            .unwrap();
+        db.conn
+            .execute(
+                "INSERT INTO Albums (album_id, album_title, artist_id) VALUES (1, 'Album', 1)",
+                params![],
+            )
+            .unwrap();
+        for id in 1..=4 {
+            add_song(&db, id)
+        }
+        let playlist = Playlist::create(&db, "Mix").unwrap();
+        playlist.add(&db, &[1, 2, 3, 4]).unwrap();
+
+        // song 2 is on a folder that went away, the playlist shows 1 3 4
+        db.conn
+            .execute("DELETE FROM Songs WHERE song_id = 2", params![])
+            .unwrap();
+        assert!(playlist.remove(&db, 0).unwrap());
+        playlist.move_range(&db, 1, 1, 0).unwrap();
+        playlist.add(&db, &[1]).unwrap();
+        assert!(!playlist.remove(&db, 3).unwrap());
+        let ids =
+            |db: &DB| -> Vec<u32> { playlist.songs(db).iter().map(|song| song.song_id).collect() };
+        assert_eq!(ids(&db), [4, 3, 1]);
+
+        add_song(&db, 2);
+        assert_eq!(ids(&db), [2, 4, 3, 1]);
+        assert_eq!(Playlist::by_id(&db, playlist.playlist_id).unwrap().songs, 4);
+    }
+}
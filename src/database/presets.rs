@@ -0,0 +1,102 @@
+use rusqlite::{params, OptionalExtension};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::player::Eq;
+
+use super::{DBObject, DB};
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Preset {
+    pub preset_id: u32,
+    pub name: String,
+    pub eq: Eq,
+}
+impl DBObject for Preset {
+    fn from_row(row: &rusqlite::Row) -> Result<Self, rusqlite::Error>
+    where
+        Self: Sized,
+    {
+        let eq: String = row.get(2)?;
+        Ok(Preset {
+            preset_id: row.get(0)?,
+            name: row.get(1)?,
+            eq: serde_json::from_str(&eq).unwrap_or_default(),
+        })
+    }
+}
+
+impl Preset {
+    pub fn all(db: &DB) -> Vec<Preset> {
+        db.query(
+            r#"
+        SELECT preset_id, preset_name, preset_eq
+        FROM EqPresets
+        ORDER BY preset_name
+        "#,
+            params![],
+        )
+    }
+    pub fn by_name(db: &DB, name: &str) -> Option<Preset> {
+        db.query(
+            r#"
+        SELECT preset_id, preset_name, preset_eq
+        FROM EqPresets
+        WHERE preset_name = ?1
+        "#,
+            params![name],
+        )
+        .first()
+        .cloned()
+    }
+    /// The preset last selected for the output identified by `output`.
+    pub fn by_output(db: &DB, output: &str) -> Option<Preset> {
+        db.query(
+            r#"
+        SELECT p.preset_id, p.preset_name, p.preset_eq
+        FROM EqOutputs o
+        JOIN EqPresets p ON o.preset_id = p.preset_id
+        WHERE o.output = ?1
+        "#,
+            params![output],
+        )
+        .first()
+        .cloned()
+    }
+    /// Creates the preset or overwrites the one with the same name.
+    pub fn save(db: &DB, name: &str, eq: &Eq) -> Result<Preset, rusqlite::Error> {
+        db.conn.execute(
+            r#"
+            INSERT INTO EqPresets (preset_name, preset_eq) VALUES (?1, ?2)
+            ON CONFLICT (preset_name) DO UPDATE SET preset_eq = excluded.preset_eq
+            "#,
+            params![name, serde_json::to_string(eq).unwrap()],
+        )?;
+        Preset::by_name(db, name).ok_or(rusqlite::Error::QueryReturnedNoRows)
+    }
+    pub fn delete(db: &DB, name: &str) -> Result<bool, rusqlite::Error> {
+        let id: Option<u32> = db
+            .conn
+            .query_row(
+                "SELECT preset_id FROM EqPresets WHERE preset_name = ?1",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(id) = id else {
+            return Ok(false);
+        };
+        db.conn
+            .execute("DELETE FROM EqOutputs WHERE preset_id = ?1", params![id])?;
+        db.conn
+            .execute("DELETE FROM EqPresets WHERE preset_id = ?1", params![id])?;
+        Ok(true)
+    }
+    pub fn select(&self, db: &DB, output: &str) -> Result<(), rusqlite::Error> {
+        db.conn.execute(
+            "INSERT OR REPLACE INTO EqOutputs (output, preset_id) VALUES (?1, ?2)",
+            params![output, self.preset_id],
+        )?;
+        Ok(())
+    }
+}
@@ -13,8 +13,23 @@ pub struct Song {
     pub artist: String,
     pub album: String,
     pub ms: u32,
+    /// title, artist, album or track number were guessed from the file's path
+    pub inferred: bool,
     #[serde(skip)]
     pub file: String,
+    /// bytes on disk when the file was last scanned
+    #[serde(skip)]
+    pub size: u64,
+    #[serde(skip)]
+    pub gain: Gain,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Gain {
+    pub track_gain: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>,
 }
 impl DBObject for Song {
     fn from_row(row: &rusqlite::Row) -> Result<Self, rusqlite::Error>
@@ -29,7 +44,15 @@ impl DBObject for Song {
             artist: row.get(4)?,
             album: row.get(5)?,
             ms: row.get(6)?,
+            inferred: row.get::<_, Option<bool>>(12)?.unwrap_or_default(),
             file: row.get(7)?,
+            size: row.get::<_, Option<u64>>(13)?.unwrap_or_default(),
+            gain: Gain {
+                track_gain: row.get(8)?,
+                track_peak: row.get(9)?,
+                album_gain: row.get(10)?,
+                album_peak: row.get(11)?,
+            },
         })
     }
 }
@@ -38,7 +61,9 @@ impl Song {
     pub fn by_id(db: &DB, id: u32) -> Option<Song> {
         db.query(
             r#"
-        SELECT s.song_id, s.artist_id, s.album_id, s.song_title, ar.artist_name, al.album_title, s.song_ms, s.song_file
+        SELECT s.song_id, s.artist_id, s.album_id, s.song_title, ar.artist_name, al.album_title, s.song_ms, s.song_file,
+            s.song_track_gain, s.song_track_peak, s.song_album_gain, s.song_album_peak, s.song_inferred,
+            s.song_size
         FROM Songs s
         JOIN Artists ar ON s.artist_id = ar.artist_id
         JOIN Albums al ON s.album_id = al.album_id
@@ -46,13 +71,15 @@ impl Song {
         "#,
             params![id],
         )
-        .get(0)
+        .first()
         .cloned()
     }
     pub fn by_title(db: &DB, title: &str) -> Vec<Song> {
         db.query(
             r#"
-        SELECT s.song_id, s.artist_id, s.album_id, s.song_title, ar.artist_name, al.album_title, s.song_ms, s.song_file
+        SELECT s.song_id, s.artist_id, s.album_id, s.song_title, ar.artist_name, al.album_title, s.song_ms, s.song_file,
+            s.song_track_gain, s.song_track_peak, s.song_album_gain, s.song_album_peak, s.song_inferred,
+            s.song_size
         FROM Songs s
         JOIN Artists ar ON s.artist_id = ar.artist_id
         JOIN Albums al ON s.album_id = al.album_id
@@ -65,7 +92,9 @@ impl Song {
     pub fn by_album_id(db: &DB, id: u32) -> Vec<Song> {
         db.query(
             r#"
-        SELECT s.song_id, s.artist_id, s.album_id, s.song_title, ar.artist_name, al.album_title, s.song_ms, s.song_file
+        SELECT s.song_id, s.artist_id, s.album_id, s.song_title, ar.artist_name, al.album_title, s.song_ms, s.song_file,
+            s.song_track_gain, s.song_track_peak, s.song_album_gain, s.song_album_peak, s.song_inferred,
+            s.song_size
         FROM Songs s
         JOIN Artists ar ON s.artist_id = ar.artist_id
         JOIN Albums al ON s.album_id = al.album_id
@@ -75,4 +104,37 @@ impl Song {
             params![id],
         )
     }
+    pub fn by_file(db: &DB, file: &str) -> Option<Song> {
+        db.query(
+            r#"
+        SELECT s.song_id, s.artist_id, s.album_id, s.song_title, ar.artist_name, al.album_title, s.song_ms, s.song_file,
+            s.song_track_gain, s.song_track_peak, s.song_album_gain, s.song_album_peak, s.song_inferred,
+            s.song_size
+        FROM Songs s
+        JOIN Artists ar ON s.artist_id = ar.artist_id
+        JOIN Albums al ON s.album_id = al.album_id
+        WHERE s.song_file = ?1
+        "#,
+            params![file],
+        )
+        .first()
+        .cloned()
+    }
+    /// The song at `path`, or every song below it when `path` is a directory.
+    pub fn by_path(db: &DB, path: &str) -> Vec<Song> {
+        let path = path.trim_end_matches('/');
+        db.query(
+            r#"
+        SELECT s.song_id, s.artist_id, s.album_id, s.song_title, ar.artist_name, al.album_title, s.song_ms, s.song_file,
+            s.song_track_gain, s.song_track_peak, s.song_album_gain, s.song_album_peak, s.song_inferred,
+            s.song_size
+        FROM Songs s
+        JOIN Artists ar ON s.artist_id = ar.artist_id
+        JOIN Albums al ON s.album_id = al.album_id
+        WHERE s.song_file = ?1 OR substr(s.song_file, 1, length(?1) + 1) = ?1 || '/'
+        ORDER BY s.song_file
+        "#,
+            params![path],
+        )
+    }
 }
@@ -0,0 +1,97 @@
+use rusqlite::{params, OptionalExtension};
+
+use crate::player::Mode;
+
+use super::DB;
+
+/// What the player was doing when it was last saved.
+#[derive(Debug, Clone)]
+pub struct State {
+    pub songs: Vec<u32>,
+    pub index: usize,
+    pub order: Vec<usize>,
+    pub position_ms: u64,
+    pub pause: bool,
+    pub mode: Mode,
+}
+
+impl State {
+    /// None when nothing was saved or the database can not be read, the queue then starts out empty.
+    pub fn load(db: &DB) -> Option<State> {
+        let songs = db
+            .conn
+            .prepare("SELECT song_id FROM QueueSongs ORDER BY queue_pos")
+            .ok()?
+            .query_map(params![], |row| row.get(0))
+            .ok()?
+            .filter_map(|id| id.ok())
+            .collect();
+        db.conn
+            .query_row(
+                r#"
+                SELECT state_index, state_order, state_position, state_pause, state_mode
+                FROM PlayerState
+                WHERE state_id = 0
+                "#,
+                params![],
+                |row| {
+                    let order: String = row.get(1)?;
+                    let mode: String = row.get(4)?;
+                    Ok(State {
+                        songs,
+                        index: row.get(0)?,
+                        order: serde_json::from_str(&order).unwrap_or_default(),
+                        position_ms: row.get(2)?,
+                        pause: row.get(3)?,
+                        mode: serde_json::from_str(&mode).unwrap_or_default(),
+                    })
+                },
+            )
+            .optional()
+            .ok()?
+    }
+    /// Follows the saved queue losing some of its songs, `map` holds the new position of every saved one.
+    /// When the current song is gone playback continues with the next one left, from its start.
+    pub fn remap(&mut self, map: &[Option<usize>]) {
+        let new = |i: &usize| map.get(*i).copied().flatten();
+        let from = self.order.iter().position(|i| *i == self.index);
+        let index = match new(&self.index) {
+            Some(index) => index,
+            None => {
+                self.position_ms = 0;
+                let after = from.map_or(&[][..], |from| &self.order[from..]);
+                let kept = map.iter().flatten().count();
+                after.iter().find_map(new).unwrap_or(kept)
+            }
+        };
+        self.songs = (0..map.len())
+            .filter(|i| new(i).is_some())
+            .map(|i| self.songs[i])
+            .collect();
+        self.order = self.order.iter().filter_map(new).collect();
+        self.index = index;
+    }
+    pub fn save(&self, db: &DB) -> Result<(), rusqlite::Error> {
+        let json = |err: serde_json::Error| rusqlite::Error::ToSqlConversionFailure(err.into());
+        let order = serde_json::to_string(&self.order).map_err(json)?;
+        let mode = serde_json::to_string(&self.mode).map_err(json)?;
+        let tx = db.conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM QueueSongs", params![])?;
+        {
+            let mut insert =
+                tx.prepare("INSERT INTO QueueSongs (queue_pos, song_id) VALUES (?1, ?2)")?;
+            for (pos, id) in self.songs.iter().enumerate() {
+                insert.execute(params![pos, id])?;
+            }
+        }
+        tx.execute(
+            r#"
+            INSERT OR REPLACE INTO PlayerState
+            (state_id, state_index, state_order, state_position, state_pause, state_mode)
+            VALUES (0, ?1, ?2, ?3, ?4, ?5)
+            "#,
+            params![self.index, order, self.position_ms, self.pause, mode],
+        )?;
+        tx.commit()
+    }
+}
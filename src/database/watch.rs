@@ -0,0 +1,85 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread::spawn,
+    time::Duration,
+};
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use super::DB;
+
+/// How long the music folders have to stay quiet before collected changes are applied,
+/// so a file being copied is only read once it is complete.
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Keeps the songs below `roots` in sync with the filesystem until the returned watcher is dropped,
+/// calling `changed` with every path whose songs changed.
+pub fn watch(
+    db: Arc<Mutex<DB>>,
+    roots: &[String],
+    changed: impl Fn(&str) + Send + 'static,
+) -> Result<RecommendedWatcher, notify::Error> {
+    let (snd, rec) = channel();
+    let mut watcher = notify::recommended_watcher(snd)?;
+    for root in roots {
+        watcher.watch(Path::new(root), RecursiveMode::Recursive)?
+    }
+    let roots = roots.to_vec();
+    spawn(move || apply(db, roots, rec, changed));
+    Ok(watcher)
+}
+
+/// Collects changed paths and applies them once no change came in for `DEBOUNCE`,
+/// returns when the watcher is dropped.
+fn apply(
+    db: Arc<Mutex<DB>>,
+    roots: Vec<String>,
+    rec: Receiver<notify::Result<notify::Event>>,
+    changed: impl Fn(&str),
+) {
+    let mut pending: BTreeSet<PathBuf> = BTreeSet::new();
+    loop {
+        let received = match pending.is_empty() {
+            true => rec.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            false => rec.recv_timeout(DEBOUNCE),
+        };
+        match received {
+            Ok(Ok(event)) => {
+                if matches!(
+                    event.kind,
+                    EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+                ) {
+                    pending.extend(event.paths)
+                }
+            }
+            Ok(Err(err)) => eprintln!("watching the music folders failed: {err}"),
+            Err(RecvTimeoutError::Timeout) => {
+                // paths that are gone go last, so a moved file is matched to its song before it is dropped
+                let (present, gone): (Vec<_>, Vec<_>) = std::mem::take(&mut pending)
+                    .into_iter()
+                    .partition(|path| path.exists());
+                for path in present.into_iter().chain(gone) {
+                    let Some(path) = path.to_str() else {
+                        continue;
+                    };
+                    let Some(root) = roots.iter().find(|root| Path::new(path).starts_with(root))
+                    else {
+                        continue;
+                    };
+                    let refreshed = db.lock().unwrap().refresh(root, path);
+                    match refreshed {
+                        Ok(true) => changed(path),
+                        Ok(false) => {}
+                        Err(err) => eprintln!("could not update {path}: {err}"),
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
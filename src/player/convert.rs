@@ -0,0 +1,144 @@
+use rubato::{
+    ResampleError, Resampler, ResamplerConstructionError, SincFixedIn, SincInterpolationParameters,
+    SincInterpolationType, WindowFunction,
+};
+use symphonia::core::audio::Channels;
+
+use super::matrix::matrix;
+
+const CHUNK: usize = 1024;
+
+/// Converts interleaved samples from the format of a file to the format the output was opened with.
+pub struct Converter {
+    channels_in: usize,
+    channels_out: usize,
+    matrix: Vec<Vec<f32>>,
+    ratio: f64,
+    resampler: Option<SincFixedIn<f32>>,
+    input: Vec<Vec<f32>>,
+    output: Vec<Vec<f32>>,
+    frames_in: usize,
+    frames_out: usize,
+}
+
+impl Converter {
+    pub fn new(
+        layout: Channels,
+        rate_in: u32,
+        channels_out: u8,
+        rate_out: u32,
+        mono: bool,
+    ) -> Result<Converter, ResamplerConstructionError> {
+        let matrix = matrix(layout, channels_out, mono);
+        let channels_out = channels_out as usize;
+        let ratio = rate_out as f64 / rate_in as f64;
+        let resampler = match rate_in == rate_out {
+            true => None,
+            false => Some(SincFixedIn::new(
+                ratio,
+                1.,
+                SincInterpolationParameters {
+                    sinc_len: 256,
+                    f_cutoff: 0.95,
+                    oversampling_factor: 256,
+                    interpolation: SincInterpolationType::Cubic,
+                    window: WindowFunction::BlackmanHarris2,
+                },
+                CHUNK,
+                channels_out,
+            )?),
+        };
+        let output = match &resampler {
+            Some(resampler) => resampler.output_buffer_allocate(true),
+            None => vec![],
+        };
+        let mut converter = Converter {
+            channels_in: layout.count(),
+            channels_out,
+            matrix,
+            ratio,
+            resampler,
+            input: vec![vec![]; channels_out],
+            output,
+            frames_in: 0,
+            frames_out: 0,
+        };
+        converter.reset();
+        Ok(converter)
+    }
+    /// Drops everything buffered, used after seeking.
+    pub fn reset(&mut self) {
+        self.input.iter_mut().for_each(|channel| channel.clear());
+        self.frames_in = 0;
+        self.frames_out = 0;
+        if let Some(resampler) = &mut self.resampler {
+            resampler.reset()
+        }
+    }
+    fn map(&self, frame: &[f32], channel: usize) -> f32 {
+        self.matrix[channel]
+            .iter()
+            .zip(frame)
+            .map(|(weight, sample)| weight * sample)
+            .sum()
+    }
+    /// Converts `samples` and appends the result to `out`.
+    pub fn push(&mut self, samples: &[f32], out: &mut Vec<f32>) -> Result<(), ResampleError> {
+        if self.resampler.is_none() {
+            for frame in samples.chunks(self.channels_in) {
+                for channel in 0..self.channels_out {
+                    out.push(self.map(frame, channel))
+                }
+            }
+            return Ok(());
+        }
+        for frame in samples.chunks(self.channels_in) {
+            for channel in 0..self.channels_out {
+                let sample = self.map(frame, channel);
+                self.input[channel].push(sample)
+            }
+            self.frames_in += 1;
+        }
+        loop {
+            let resampler = self.resampler.as_mut().unwrap();
+            if self.input[0].len() < resampler.input_frames_next() {
+                break;
+            }
+            let (read, written) =
+                resampler.process_into_buffer(&self.input, &mut self.output, None)?;
+            self.input.iter_mut().for_each(|channel| {
+                channel.drain(..read);
+            });
+            self.emit(written, usize::MAX, out)
+        }
+        Ok(())
+    }
+    /// Drains the resampler once the file has ended.
+    pub fn flush(&mut self, out: &mut Vec<f32>) -> Result<(), ResampleError> {
+        if self.resampler.is_none() {
+            return Ok(());
+        }
+        let expected = (self.frames_in as f64 * self.ratio).round() as usize;
+        while self.frames_out < expected {
+            let resampler = self.resampler.as_mut().unwrap();
+            let (read, written) =
+                resampler.process_partial_into_buffer(Some(&self.input), &mut self.output, None)?;
+            self.input.iter_mut().for_each(|channel| {
+                channel.drain(..read.min(channel.len()));
+            });
+            self.emit(written, expected, out)
+        }
+        Ok(())
+    }
+    fn emit(&mut self, written: usize, limit: usize, out: &mut Vec<f32>) {
+        for frame in 0..written {
+            if self.frames_out >= limit {
+                break;
+            }
+            for channel in 0..self.channels_out {
+                out.push(self.output[channel][frame])
+            }
+            self.frames_out += 1;
+        }
+    }
+}
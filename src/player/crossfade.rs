@@ -0,0 +1,65 @@
+use std::{f32::consts::FRAC_PI_2, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Curve {
+    #[default]
+    Linear,
+    EqualPower,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, ToSchema)]
+pub struct Crossfade {
+    /// 0 disables crossfading
+    pub secs: f32,
+    pub curve: Curve,
+}
+
+impl Crossfade {
+    /// Longest crossfade accepted.
+    pub const MAX_SECS: f32 = 30.;
+
+    /// The crossfade with `secs` limited to 0..=MAX_SECS, None when `secs` is not a finite number.
+    pub fn checked(self) -> Option<Crossfade> {
+        self.secs.is_finite().then_some(Crossfade {
+            secs: self.secs.clamp(0., Crossfade::MAX_SECS),
+            curve: self.curve,
+        })
+    }
+    pub fn enabled(&self) -> bool {
+        self.secs > 0.
+    }
+    /// Never panics, it is called from the audio callback.
+    pub fn duration(&self) -> Duration {
+        Duration::try_from_secs_f32(self.secs.clamp(0., Crossfade::MAX_SECS)).unwrap_or_default()
+    }
+}
+
+/// Progress of a running crossfade, counted in frames.
+pub struct Fade {
+    curve: Curve,
+    frames: u64,
+    pos: u64,
+}
+
+impl Fade {
+    pub fn new(curve: Curve, frames: u64) -> Fade {
+        Fade {
+            curve,
+            frames: frames.max(1),
+            pos: 0,
+        }
+    }
+    /// Returns the gains of the outgoing and incoming source for the next frame.
+    pub fn step(&mut self) -> (f32, f32) {
+        let t = (self.pos as f32 / self.frames as f32).min(1.);
+        self.pos += 1;
+        match self.curve {
+            Curve::Linear => (1. - t, t),
+            Curve::EqualPower => ((t * FRAC_PI_2).cos(), (t * FRAC_PI_2).sin()),
+        }
+    }
+}
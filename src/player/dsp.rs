@@ -0,0 +1,143 @@
+use std::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// A stage run over the mixed output before the volume is applied.
+pub trait Dsp: Send {
+    fn process(&mut self, data: &mut [f32]);
+}
+
+pub type Chain = Vec<Box<dyn Dsp>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Filter {
+    Peaking,
+    LowShelf,
+    HighShelf,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, ToSchema)]
+pub struct Band {
+    pub filter: Filter,
+    /// centre or corner frequency in Hz
+    pub freq: f32,
+    /// dB
+    pub gain: f32,
+    pub q: f32,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, ToSchema)]
+pub struct Eq {
+    pub enabled: bool,
+    /// dB applied before the bands, lower it to make room for boosts
+    pub preamp: f32,
+    pub bands: Vec<Band>,
+}
+
+impl Eq {
+    /// Builds the DSP chain for the output, empty when there is nothing to do.
+    pub fn chain(&self, channels: u8, sample_rate: u32) -> Chain {
+        let mut chain: Chain = vec![];
+        if self.enabled && (!self.bands.is_empty() || self.preamp != 0.) {
+            chain.push(Box::new(Equalizer::new(self, channels, sample_rate)))
+        }
+        chain
+    }
+}
+
+/// Biquad coefficients normalized by a0, from the RBJ audio EQ cookbook.
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Biquad {
+    fn new(band: &Band, sample_rate: u32) -> Biquad {
+        let a = 10f32.powf(band.gain / 40.);
+        let w0 = 2. * PI * band.freq.clamp(1., sample_rate as f32 / 2. - 1.) / sample_rate as f32;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2. * band.q.max(0.01));
+        let sqrt = 2. * a.sqrt() * alpha;
+        let (b0, b1, b2, a0, a1, a2) = match band.filter {
+            Filter::Peaking => (
+                1. + alpha * a,
+                -2. * cos,
+                1. - alpha * a,
+                1. + alpha / a,
+                -2. * cos,
+                1. - alpha / a,
+            ),
+            Filter::LowShelf => (
+                a * ((a + 1.) - (a - 1.) * cos + sqrt),
+                2. * a * ((a - 1.) - (a + 1.) * cos),
+                a * ((a + 1.) - (a - 1.) * cos - sqrt),
+                (a + 1.) + (a - 1.) * cos + sqrt,
+                -2. * ((a - 1.) + (a + 1.) * cos),
+                (a + 1.) + (a - 1.) * cos - sqrt,
+            ),
+            Filter::HighShelf => (
+                a * ((a + 1.) + (a - 1.) * cos + sqrt),
+                -2. * a * ((a - 1.) + (a + 1.) * cos),
+                a * ((a + 1.) + (a - 1.) * cos - sqrt),
+                (a + 1.) - (a - 1.) * cos + sqrt,
+                2. * ((a - 1.) - (a + 1.) * cos),
+                (a + 1.) - (a - 1.) * cos - sqrt,
+            ),
+        };
+        Biquad {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+}
+
+pub struct Equalizer {
+    channels: usize,
+    preamp: f32,
+    filters: Vec<Biquad>,
+    /// transposed direct form II state, two values per filter and channel
+    state: Vec<[f32; 2]>,
+}
+
+impl Equalizer {
+    pub fn new(eq: &Eq, channels: u8, sample_rate: u32) -> Equalizer {
+        let filters: Vec<Biquad> = eq
+            .bands
+            .iter()
+            .map(|band| Biquad::new(band, sample_rate))
+            .collect();
+        Equalizer {
+            channels: channels as usize,
+            preamp: 10f32.powf(eq.preamp / 20.),
+            state: vec![[0.; 2]; filters.len() * channels as usize],
+            filters,
+        }
+    }
+}
+
+impl Dsp for Equalizer {
+    fn process(&mut self, data: &mut [f32]) {
+        for frame in data.chunks_mut(self.channels) {
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let mut x = *sample * self.preamp;
+                for (i, f) in self.filters.iter().enumerate() {
+                    let s = &mut self.state[i * self.channels + channel];
+                    let y = f.b0 * x + s[0];
+                    s[0] = f.b1 * x - f.a1 * y + s[1];
+                    s[1] = f.b2 * x - f.a2 * y;
+                    x = y;
+                }
+                *sample = x
+            }
+        }
+    }
+}
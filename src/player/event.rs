@@ -0,0 +1,64 @@
+use serde::Serialize;
+use tokio::sync::broadcast;
+use utoipa::ToSchema;
+
+use crate::database::Song;
+
+use super::{Mode, Volume};
+
+/// Sending half of the event bus, receivers subscribe through it.
+pub type Events = broadcast::Sender<Event>;
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    TrackStarted {
+        index: usize,
+        song: Song,
+    },
+    TrackEnded {
+        song_id: u32,
+    },
+    Paused {
+        pause: bool,
+    },
+    /// playback stopped on the current song
+    Stopped,
+    /// position in ms
+    Seeked {
+        pos: u128,
+    },
+    QueueChanged {
+        index: usize,
+        songs: usize,
+        mode: Mode,
+    },
+    VolumeChanged {
+        volume: Volume,
+    },
+    /// a song failed to decode, `skipped` once playback gave up on it
+    PlaybackError {
+        song_id: u32,
+        error: String,
+        skipped: bool,
+    },
+    /// songs below `path` were added, changed or removed while running
+    LibraryChanged {
+        path: String,
+    },
+    /// sent while scanning a music root, `done == total` once it is finished
+    Scan {
+        path: String,
+        done: usize,
+        total: usize,
+    },
+}
+
+pub fn bus() -> Events {
+    broadcast::channel(256).0
+}
+
+/// Publishes `event`, it is fine if nobody listens.
+pub fn emit(events: &Events, event: Event) {
+    let _ = events.send(event);
+}
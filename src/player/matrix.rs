@@ -0,0 +1,143 @@
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use symphonia::core::audio::Channels;
+
+/// Speaker positions of an output with `count` channels, in WAVE/ALSA order.
+fn layout(count: u8) -> Vec<Channels> {
+    match count {
+        1 => vec![Channels::FRONT_CENTRE],
+        4 => vec![
+            Channels::FRONT_LEFT,
+            Channels::FRONT_RIGHT,
+            Channels::REAR_LEFT,
+            Channels::REAR_RIGHT,
+        ],
+        _ => [
+            Channels::FRONT_LEFT,
+            Channels::FRONT_RIGHT,
+            Channels::FRONT_CENTRE,
+            Channels::LFE1,
+            Channels::REAR_LEFT,
+            Channels::REAR_RIGHT,
+            Channels::SIDE_LEFT,
+            Channels::SIDE_RIGHT,
+        ]
+        .into_iter()
+        .take(count as usize)
+        .collect(),
+    }
+}
+
+fn is_left(pos: Channels) -> bool {
+    (Channels::FRONT_LEFT
+        | Channels::REAR_LEFT
+        | Channels::FRONT_LEFT_CENTRE
+        | Channels::SIDE_LEFT
+        | Channels::TOP_FRONT_LEFT
+        | Channels::TOP_REAR_LEFT
+        | Channels::REAR_LEFT_CENTRE
+        | Channels::FRONT_LEFT_WIDE
+        | Channels::FRONT_LEFT_HIGH)
+        .contains(pos)
+}
+
+fn is_right(pos: Channels) -> bool {
+    (Channels::FRONT_RIGHT
+        | Channels::REAR_RIGHT
+        | Channels::FRONT_RIGHT_CENTRE
+        | Channels::SIDE_RIGHT
+        | Channels::TOP_FRONT_RIGHT
+        | Channels::TOP_REAR_RIGHT
+        | Channels::REAR_RIGHT_CENTRE
+        | Channels::FRONT_RIGHT_WIDE
+        | Channels::FRONT_RIGHT_HIGH)
+        .contains(pos)
+}
+
+fn is_front(pos: Channels) -> bool {
+    (Channels::FRONT_LEFT
+        | Channels::FRONT_RIGHT
+        | Channels::FRONT_LEFT_CENTRE
+        | Channels::FRONT_RIGHT_CENTRE
+        | Channels::FRONT_LEFT_WIDE
+        | Channels::FRONT_RIGHT_WIDE)
+        .contains(pos)
+}
+
+/// Where a channel missing from the output ends up, as (output position, weight).
+fn fold(pos: Channels, out: &[Channels]) -> Vec<(Channels, f32)> {
+    let has = |pos: Channels| out.contains(&pos);
+    if has(pos) {
+        return vec![(pos, 1.)];
+    }
+    if (Channels::LFE1 | Channels::LFE2).contains(pos) {
+        return match has(Channels::LFE1) {
+            true => vec![(Channels::LFE1, 1.)],
+            false => vec![],
+        };
+    }
+    if !has(Channels::FRONT_LEFT) || !has(Channels::FRONT_RIGHT) {
+        return vec![(Channels::FRONT_CENTRE, 1.)];
+    }
+    let swapped = match pos {
+        Channels::REAR_LEFT => Channels::SIDE_LEFT,
+        Channels::SIDE_LEFT => Channels::REAR_LEFT,
+        Channels::REAR_RIGHT => Channels::SIDE_RIGHT,
+        Channels::SIDE_RIGHT => Channels::REAR_RIGHT,
+        _ => Channels::empty(),
+    };
+    if !swapped.is_empty() && has(swapped) {
+        return vec![(swapped, 1.)];
+    }
+    let weight = match is_front(pos) {
+        true => 1.,
+        false => FRAC_1_SQRT_2,
+    };
+    if is_left(pos) {
+        vec![(Channels::FRONT_LEFT, weight)]
+    } else if is_right(pos) {
+        vec![(Channels::FRONT_RIGHT, weight)]
+    } else {
+        vec![
+            (Channels::FRONT_LEFT, FRAC_1_SQRT_2),
+            (Channels::FRONT_RIGHT, FRAC_1_SQRT_2),
+        ]
+    }
+}
+
+/// Builds the `[output][input]` gains mixing `input` down or up to `channels`,
+/// scaled so that no output channel can clip. Mono files and everything with
+/// `mono` set end up as one signal sent to the front left and right speakers.
+pub fn matrix(input: Channels, channels: u8, mono: bool) -> Vec<Vec<f32>> {
+    let out = layout(channels);
+    let mono = mono || input.count() == 1;
+    let folded = match mono {
+        true => vec![Channels::FRONT_CENTRE],
+        false => out.clone(),
+    };
+    let mut rows = vec![vec![0.; input.count()]; folded.len()];
+    for (i, pos) in input.iter().enumerate() {
+        for (target, weight) in fold(pos, &folded) {
+            if let Some(row) = folded.iter().position(|out| *out == target) {
+                rows[row][i] += weight
+            }
+        }
+    }
+    let loudest = rows
+        .iter()
+        .map(|row| row.iter().sum::<f32>())
+        .fold(1., f32::max);
+    rows.iter_mut()
+        .flatten()
+        .for_each(|weight| *weight /= loudest);
+    if !mono {
+        return rows;
+    }
+    let front = Channels::FRONT_LEFT | Channels::FRONT_RIGHT;
+    out.iter()
+        .map(|pos| match out.len() == 1 || front.contains(*pos) {
+            true => rows[0].clone(),
+            false => vec![0.; input.count()],
+        })
+        .collect()
+}
@@ -1,97 +1,302 @@
 use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
     fs::File,
     sync::{
         mpsc::{channel, Receiver, Sender},
-        Arc, Mutex,
+        Arc,
     },
-    thread::spawn,
+    thread::{spawn, JoinHandle},
     time::Duration,
 };
 
-use crate::database::Song;
+use serde::Serialize;
+use tokio::sync::oneshot;
+use utoipa::ToSchema;
+
+use crate::{
+    database::{Song, State, DB},
+    server::Config,
+};
 
 use self::{queue::Queue, source::Source, speaker::Speaker};
+pub use crossfade::{Crossfade, Curve};
+pub use dsp::{Band, Eq, Filter};
+pub use event::{bus, emit, Event, Events};
+pub use output::Backend;
+pub use queue::{Mode, Repeat};
+pub use replaygain::{GainMode, ReplayGain};
+pub use volume::Volume;
+mod convert;
+mod crossfade;
+mod dsp;
+mod event;
+mod matrix;
+mod output;
 mod queue;
+mod replaygain;
 pub mod source;
 mod speaker;
+mod volume;
+
+/// The thread answers on a channel per request, so concurrent callers never see each other's replies.
+type Reply<T> = oneshot::Sender<T>;
 
 enum Cmd {
     Play,
     Push(Song),
+    Edit(Edit),
     Next,
     Prev,
     Index(usize),
     Delete(usize),
-    Pause,
     SetPause(bool),
-    Duration,
-    Position,
     SetPosition(Duration),
-    Queue,
-    Ended,
-    Now,
+    Stop,
+    Queue(Reply<Queue>),
+    /// the source the speaker went on with, if any
+    Ended(Option<Arc<Source>>),
+    Failed(u32, String, bool),
+    Errors(Reply<Vec<SongError>>),
+    Status(Reply<Status>),
+    Crossfade(Reply<Crossfade>),
+    SetCrossfade(Crossfade),
+    ReplayGain(Reply<ReplayGain>),
+    SetReplayGain(ReplayGain),
+    Volume(Reply<Volume>),
+    SetVolume(Volume),
+    Eq(Reply<Eq>),
+    SetEq(Eq),
+    Mode(Reply<Mode>),
+    SetMode(Mode),
+    Restore(Queue, Duration, bool),
     Die,
 }
-enum Rpl {
-    Is(bool),
-    Time(Duration),
-    Queue(Queue),
-    Now(Option<(u32, String)>),
+
+/// Queue changes that only restart playback when the current song is affected.
+enum Edit {
+    Insert(usize, Vec<Song>),
+    PlayNext(Vec<Song>),
+    Move(usize, usize, usize),
+    Replace(Vec<Song>),
+    Clear,
+    Crop,
+}
+
+/// Playback state taken in one go, so its fields agree with each other.
+#[derive(Debug, Clone)]
+pub struct Status {
+    pub index: usize,
+    /// None once the queue has played out
+    pub song: Option<Song>,
+    pub position: Duration,
+    /// zero while nothing is loaded
+    pub duration: Duration,
+    pub paused: bool,
+    pub volume: Volume,
+    /// times the output ran out of decoded samples since startup
+    pub underruns: u64,
+}
+
+/// What went wrong while playing a song, kept until the player stops.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SongError {
+    pub song_id: u32,
+    /// corrupt packets and failed opens so far
+    pub count: u32,
+    pub last: String,
+    /// playback gave up on the song and moved on
+    pub skipped: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    /// the player thread is gone, which only happens while shutting down
+    Stopped,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Stopped => write!(f, "the player has stopped"),
+        }
+    }
 }
+
+impl std::error::Error for Error {}
+
+/// Handle to the player thread, cheap to clone and safe to share between requests.
+#[derive(Clone)]
 pub struct Player {
     cmd: Sender<Cmd>,
-    rpl: Receiver<Rpl>,
+    _thread: Arc<Thread>,
 }
+
+/// Stops the player thread once the last handle is dropped.
+struct Thread {
+    cmd: Sender<Cmd>,
+    handle: Option<JoinHandle<()>>,
+}
+
 impl Player {
-    pub fn new() -> Player {
+    pub fn new(conf: &Config, events: Events) -> Player {
         let (cmd1, cmd2) = channel();
-        let (rpl1, rpl2) = channel();
-        Player::run(cmd1.clone(), cmd2, rpl1);
+        let handle = Player::run(conf.clone(), events, cmd1.clone(), cmd2);
         Player {
-            cmd: cmd1,
-            rpl: rpl2,
+            cmd: cmd1.clone(),
+            _thread: Arc::new(Thread {
+                cmd: cmd1,
+                handle: Some(handle),
+            }),
         }
     }
-    fn run(cmd_snd: Sender<Cmd>, cmd: Receiver<Cmd>, rpl: Sender<Rpl>) {
+    fn run(
+        conf: Config,
+        events: Events,
+        cmd_snd: Sender<Cmd>,
+        cmd: Receiver<Cmd>,
+    ) -> JoinHandle<()> {
         spawn(move || {
             let play = || {
                 cmd_snd.clone().send(Cmd::Play).unwrap();
             };
             let end = cmd_snd.clone();
-            let on_end = Box::new(move || {
-                end.send(Cmd::Ended).unwrap();
+            let on_end = Box::new(move |switched| {
+                end.send(Cmd::Ended(switched)).unwrap();
             });
 
+            let mut crossfade = conf.crossfade.checked().unwrap_or_default();
+            let mut replaygain = conf.replaygain;
+            let mut volume = conf.volume.clamped();
+            let mut eq = Eq::default();
             let mut queue = Queue::new();
-            let mut source: Option<Arc<Mutex<Source>>> = None;
-            let mut channels = 2;
-            let mut sample_rate = 48_000;
-            let mut speaker = Speaker::new(channels, sample_rate, on_end.clone());
+            queue.set_mode(conf.mode);
+            let mut source: Option<Arc<Source>> = None;
+            let mut next: Option<(u32, Arc<Source>)> = None;
+            // position and pause state the next source starts with, set when restoring
+            let mut pending: Option<(Duration, bool)> = None;
+            let mut errors: HashMap<u32, SongError> = HashMap::new();
+            let db = DB::open(&conf.db_path)
+                .map_err(|err| {
+                    eprintln!("could not open the database, the queue is not saved: {err}")
+                })
+                .ok();
+            let speaker = Speaker::new(&conf.output, conf.channels, conf.sample_rate, on_end);
+            speaker.set_volume(volume);
             loop {
-                match cmd.recv().unwrap() {
+                let cmd = cmd.recv().unwrap();
+                let changes = matches!(
+                    cmd,
+                    Cmd::Play
+                        | Cmd::Push(_)
+                        | Cmd::Edit(_)
+                        | Cmd::Next
+                        | Cmd::Prev
+                        | Cmd::Index(_)
+                        | Cmd::Delete(_)
+                        | Cmd::SetPause(_)
+                        | Cmd::SetPosition(_)
+                        | Cmd::Stop
+                        | Cmd::Ended(_)
+                        | Cmd::SetMode(_)
+                        | Cmd::Die
+                );
+                let mut queue_changes = changes
+                    && !matches!(
+                        cmd,
+                        Cmd::Play | Cmd::SetPause(_) | Cmd::SetPosition(_) | Cmd::Stop | Cmd::Die
+                    );
+                let die = matches!(cmd, Cmd::Die);
+                match cmd {
                     Cmd::Play => {
-                        if let Some(now) = queue.now() {
-                            if let Ok(file) = File::open(now.1) {
-                                if let Ok(src) = Source::new(file) {
-                                    if src.channels() != channels
-                                        || src.sample_rate() != sample_rate
-                                    {
-                                        channels = src.channels();
-                                        sample_rate = src.sample_rate();
-                                        speaker =
-                                            Speaker::new(channels, sample_rate, on_end.clone())
-                                    }
-                                    let src = Arc::new(Mutex::new(src));
-                                    speaker.play(src.clone());
-                                    source.replace(src);
+                        // songs that can not be opened are skipped, going around the queue at most once
+                        let mut opened = None;
+                        for _ in 0..queue.songs.len() {
+                            let Some(now) = queue.now() else {
+                                break;
+                            };
+                            let src = match next.take() {
+                                Some((id, src)) if id == now.song_id => Some(src),
+                                _ => open(&now, &speaker, &conf, &cmd_snd, true).map(Arc::new),
+                            };
+                            match src {
+                                Some(src) => {
+                                    opened = Some((now, src));
+                                    break;
+                                }
+                                None => {
+                                    queue.skip();
+                                    queue_changes = true
                                 }
                             }
-                        } else {
-                            speaker = Speaker::new(channels, sample_rate, on_end.clone())
                         }
+                        match opened {
+                            Some((now, src)) => {
+                                src.set_gain(replaygain.factor(&now, queue.in_album(queue.index)));
+                                if let Some((pos, pause)) = pending.take() {
+                                    src.set_position(pos);
+                                    src.set_pause(pause)
+                                }
+                                speaker.play(src.clone());
+                                source.replace(src);
+                                emit(
+                                    &events,
+                                    Event::TrackStarted {
+                                        index: queue.index,
+                                        song: now,
+                                    },
+                                )
+                            }
+                            None => {
+                                speaker.stop();
+                                source.take();
+                            }
+                        }
+                        preload(
+                            &queue,
+                            &speaker,
+                            &mut next,
+                            &crossfade,
+                            &replaygain,
+                            &conf,
+                            &cmd_snd,
+                        )
                     }
                     Cmd::Push(song) => {
                         queue.push(song);
+                        preload(
+                            &queue,
+                            &speaker,
+                            &mut next,
+                            &crossfade,
+                            &replaygain,
+                            &conf,
+                            &cmd_snd,
+                        )
+                    }
+                    Cmd::Edit(edit) => {
+                        let before = queue.now().map(|song| song.song_id);
+                        match edit {
+                            Edit::Insert(at, songs) => queue.insert(at, songs),
+                            Edit::PlayNext(songs) => queue.play_next(songs),
+                            Edit::Move(from, count, to) => queue.move_range(from, count, to),
+                            Edit::Replace(songs) => queue.replace(songs),
+                            Edit::Clear => queue.clear(),
+                            Edit::Crop => queue.crop(),
+                        }
+                        if source.is_some() && queue.now().map(|song| song.song_id) != before {
+                            play()
+                        } else {
+                            preload(
+                                &queue,
+                                &speaker,
+                                &mut next,
+                                &crossfade,
+                                &replaygain,
+                                &conf,
+                                &cmd_snd,
+                            )
+                        }
                     }
                     Cmd::Next => {
                         queue.next();
@@ -110,114 +315,515 @@ impl Player {
                         queue.delete(index);
                         if is_now {
                             play()
+                        } else {
+                            preload(
+                                &queue,
+                                &speaker,
+                                &mut next,
+                                &crossfade,
+                                &replaygain,
+                                &conf,
+                                &cmd_snd,
+                            )
                         }
                     }
-                    Cmd::Pause => rpl
-                        .send(Rpl::Is(match &source {
-                            Some(src) => src.lock().unwrap().paused(),
-                            None => false,
-                        }))
-                        .unwrap(),
                     Cmd::SetPause(pause) => {
                         if let Some(src) = &source {
-                            let mut lock = src.lock().unwrap();
-                            lock.set_pause(pause)
+                            src.set_pause(pause);
+                            emit(&events, Event::Paused { pause })
                         }
                     }
-                    Cmd::Duration => rpl
-                        .send(Rpl::Time(match &source {
-                            Some(src) => src.lock().unwrap().duration(),
-                            None => Duration::from_secs(0),
-                        }))
-                        .unwrap(),
-                    Cmd::Position => rpl
-                        .send(Rpl::Time(match &source {
-                            Some(src) => src.lock().unwrap().position(),
-                            None => Duration::from_secs(0),
-                        }))
-                        .unwrap(),
                     Cmd::SetPosition(pos) => {
                         if let Some(src) = &source {
-                            let mut lock = src.lock().unwrap();
-                            lock.set_position(pos)
+                            src.set_position(pos);
+                            let pos = src.heard(speaker.latency()).as_millis();
+                            emit(&events, Event::Seeked { pos })
+                        }
+                    }
+                    // the current song stays, playing again starts it over
+                    Cmd::Stop => {
+                        if source.take().is_some() {
+                            speaker.stop();
+                            emit(&events, Event::Stopped)
                         }
                     }
-                    Cmd::Queue => rpl.send(Rpl::Queue(queue.clone())).unwrap(),
-                    Cmd::Ended => {
-                        queue.remove();
-                        play();
+                    Cmd::Queue(reply) => {
+                        let _ = reply.send(queue.clone());
+                    }
+                    Cmd::Ended(switched) => {
+                        if let Some(song) = queue.now() {
+                            emit(
+                                &events,
+                                Event::TrackEnded {
+                                    song_id: song.song_id,
+                                },
+                            )
+                        }
+                        queue.ended();
+                        // the speaker may have gone on with a source the player replaced since,
+                        // it is only kept when it is the one preloaded for the song now current
+                        let now = queue.now().map(|song| song.song_id);
+                        let kept = match (&next, &switched) {
+                            (Some((id, src)), Some(switched)) => {
+                                Arc::ptr_eq(src, switched) && Some(*id) == now
+                            }
+                            _ => false,
+                        };
+                        match next.take() {
+                            Some((_, src)) if kept => {
+                                source.replace(src);
+                                if let Some(song) = queue.now() {
+                                    emit(
+                                        &events,
+                                        Event::TrackStarted {
+                                            index: queue.index,
+                                            song,
+                                        },
+                                    )
+                                }
+                                preload(
+                                    &queue,
+                                    &speaker,
+                                    &mut next,
+                                    &crossfade,
+                                    &replaygain,
+                                    &conf,
+                                    &cmd_snd,
+                                )
+                            }
+                            other => {
+                                next = other;
+                                play()
+                            }
+                        }
+                    }
+                    Cmd::Failed(song_id, error, skipped) => {
+                        let entry = errors.entry(song_id).or_insert(SongError {
+                            song_id,
+                            count: 0,
+                            last: String::new(),
+                            skipped: false,
+                        });
+                        entry.count += 1;
+                        entry.last = error.clone();
+                        entry.skipped |= skipped;
+                        emit(
+                            &events,
+                            Event::PlaybackError {
+                                song_id,
+                                error,
+                                skipped,
+                            },
+                        )
+                    }
+                    Cmd::Errors(reply) => {
+                        let mut list: Vec<SongError> = errors.values().cloned().collect();
+                        list.sort_by_key(|error| error.song_id);
+                        let _ = reply.send(list);
+                    }
+                    Cmd::Restore(restored, pos, pause) => {
+                        queue = restored;
+                        pending = Some((pos, pause));
+                        play()
+                    }
+                    Cmd::Die => {}
+                    Cmd::Status(reply) => {
+                        let (position, duration, paused) = match &source {
+                            Some(src) => {
+                                (src.heard(speaker.latency()), src.duration(), src.paused())
+                            }
+                            None => (Duration::ZERO, Duration::ZERO, false),
+                        };
+                        let _ = reply.send(Status {
+                            index: queue.index,
+                            song: queue.now(),
+                            position,
+                            duration,
+                            paused,
+                            volume,
+                            underruns: speaker.underruns(),
+                        });
+                    }
+                    Cmd::Crossfade(reply) => {
+                        let _ = reply.send(crossfade);
+                    }
+                    Cmd::Volume(reply) => {
+                        let _ = reply.send(volume);
+                    }
+                    Cmd::SetVolume(set) => {
+                        volume = set.clamped();
+                        speaker.set_volume(volume);
+                        emit(&events, Event::VolumeChanged { volume })
+                    }
+                    Cmd::Mode(reply) => {
+                        let _ = reply.send(queue.mode);
+                    }
+                    Cmd::SetMode(mode) => {
+                        queue.set_mode(mode);
+                        preload(
+                            &queue,
+                            &speaker,
+                            &mut next,
+                            &crossfade,
+                            &replaygain,
+                            &conf,
+                            &cmd_snd,
+                        )
+                    }
+                    Cmd::Eq(reply) => {
+                        let _ = reply.send(eq.clone());
+                    }
+                    Cmd::SetEq(set) => {
+                        eq = set;
+                        speaker.set_dsp(eq.chain(speaker.channels(), speaker.sample_rate()))
                     }
-                    Cmd::Die => {
-                        break;
+                    Cmd::ReplayGain(reply) => {
+                        let _ = reply.send(replaygain);
                     }
-                    Cmd::Now => rpl.send(Rpl::Now(queue.now())).unwrap(),
+                    Cmd::SetReplayGain(set) => {
+                        replaygain = set;
+                        if let (Some(src), Some(now)) = (&source, queue.now()) {
+                            src.set_gain(replaygain.factor(&now, queue.in_album(queue.index)))
+                        }
+                        preload(
+                            &queue,
+                            &speaker,
+                            &mut next,
+                            &crossfade,
+                            &replaygain,
+                            &conf,
+                            &cmd_snd,
+                        )
+                    }
+                    Cmd::SetCrossfade(set) => {
+                        crossfade = set;
+                        preload(
+                            &queue,
+                            &speaker,
+                            &mut next,
+                            &crossfade,
+                            &replaygain,
+                            &conf,
+                            &cmd_snd,
+                        )
+                    }
+                }
+                if let (true, Some(db)) = (changes, &db) {
+                    save(db, &queue, &source, speaker.latency())
+                }
+                if queue_changes {
+                    emit(
+                        &events,
+                        Event::QueueChanged {
+                            index: queue.index,
+                            songs: queue.songs.len(),
+                            mode: queue.mode,
+                        },
+                    )
+                }
+                if die {
+                    break;
                 }
             }
-        });
+        })
     }
-    pub fn play(&self) {
-        self.cmd.send(Cmd::Play).unwrap()
+    fn send(&self, cmd: Cmd) -> Result<(), Error> {
+        self.cmd.send(cmd).map_err(|_| Error::Stopped)
     }
-    pub fn push(&self, song: Song) {
-        self.cmd.send(Cmd::Push(song)).unwrap();
+    /// Sends a request and waits for its reply, which comes after every command sent before it.
+    async fn ask<T>(&self, cmd: impl FnOnce(Reply<T>) -> Cmd) -> Result<T, Error> {
+        let (reply, answer) = oneshot::channel();
+        self.send(cmd(reply))?;
+        answer.await.map_err(|_| Error::Stopped)
     }
-    pub fn index(&self, index: usize) {
-        self.cmd.send(Cmd::Index(index)).unwrap();
+    pub fn play(&self) -> Result<(), Error> {
+        self.send(Cmd::Play)
     }
-    pub fn next(&self) {
-        self.cmd.send(Cmd::Next).unwrap();
+    pub fn push(&self, song: Song) -> Result<(), Error> {
+        self.send(Cmd::Push(song))
     }
-    pub fn prev(&self) {
-        self.cmd.send(Cmd::Prev).unwrap();
+    pub fn insert(&self, at: usize, songs: Vec<Song>) -> Result<(), Error> {
+        self.send(Cmd::Edit(Edit::Insert(at, songs)))
     }
-    pub fn delete(&self, index: usize) {
-        self.cmd.send(Cmd::Delete(index)).unwrap();
+    pub fn play_next(&self, songs: Vec<Song>) -> Result<(), Error> {
+        self.send(Cmd::Edit(Edit::PlayNext(songs)))
     }
-    pub fn set_pause(&self, pause: bool) {
-        self.cmd.send(Cmd::SetPause(pause)).unwrap()
+    pub fn move_range(&self, from: usize, count: usize, to: usize) -> Result<(), Error> {
+        self.send(Cmd::Edit(Edit::Move(from, count, to)))
     }
-    pub fn set_position(&self, pos: Duration) {
-        self.cmd.send(Cmd::SetPosition(pos)).unwrap();
+    pub fn replace(&self, songs: Vec<Song>) -> Result<(), Error> {
+        self.send(Cmd::Edit(Edit::Replace(songs)))
     }
-    pub fn position(&self) -> Duration {
-        self.cmd.send(Cmd::Position).unwrap();
-        match self.rpl.recv().unwrap() {
-            Rpl::Time(dur) => dur,
-            _ => unreachable!(),
-        }
+    pub fn clear(&self) -> Result<(), Error> {
+        self.send(Cmd::Edit(Edit::Clear))
     }
-    pub fn duration(&self) -> Duration {
-        self.cmd.send(Cmd::Duration).unwrap();
-        match self.rpl.recv().unwrap() {
-            Rpl::Time(dur) => dur,
-            _ => unreachable!(),
-        }
+    pub fn crop(&self) -> Result<(), Error> {
+        self.send(Cmd::Edit(Edit::Crop))
     }
-    pub fn is_paused(&self) -> bool {
-        self.cmd.send(Cmd::Pause).unwrap();
-        match self.rpl.recv().unwrap() {
-            Rpl::Is(is) => is,
-            _ => unreachable!(),
-        }
+    pub fn index(&self, index: usize) -> Result<(), Error> {
+        self.send(Cmd::Index(index))
+    }
+    pub fn next(&self) -> Result<(), Error> {
+        self.send(Cmd::Next)
+    }
+    pub fn prev(&self) -> Result<(), Error> {
+        self.send(Cmd::Prev)
+    }
+    pub fn delete(&self, index: usize) -> Result<(), Error> {
+        self.send(Cmd::Delete(index))
+    }
+    pub fn set_pause(&self, pause: bool) -> Result<(), Error> {
+        self.send(Cmd::SetPause(pause))
+    }
+    pub fn set_position(&self, pos: Duration) -> Result<(), Error> {
+        self.send(Cmd::SetPosition(pos))
+    }
+    pub fn stop(&self) -> Result<(), Error> {
+        self.send(Cmd::Stop)
+    }
+    pub async fn status(&self) -> Result<Status, Error> {
+        self.ask(Cmd::Status).await
+    }
+    /// Songs that failed to play since startup.
+    pub async fn errors(&self) -> Result<Vec<SongError>, Error> {
+        self.ask(Cmd::Errors).await
+    }
+    pub async fn queue(&self) -> Result<Queue, Error> {
+        self.ask(Cmd::Queue).await
+    }
+    pub async fn crossfade(&self) -> Result<Crossfade, Error> {
+        self.ask(Cmd::Crossfade).await
+    }
+    pub fn set_crossfade(&self, crossfade: Crossfade) -> Result<(), Error> {
+        self.send(Cmd::SetCrossfade(crossfade))
+    }
+    pub async fn replaygain(&self) -> Result<ReplayGain, Error> {
+        self.ask(Cmd::ReplayGain).await
+    }
+    pub fn set_replaygain(&self, replaygain: ReplayGain) -> Result<(), Error> {
+        self.send(Cmd::SetReplayGain(replaygain))
+    }
+    pub async fn volume(&self) -> Result<Volume, Error> {
+        self.ask(Cmd::Volume).await
+    }
+    pub fn set_volume(&self, volume: Volume) -> Result<(), Error> {
+        self.send(Cmd::SetVolume(volume))
+    }
+    pub async fn mode(&self) -> Result<Mode, Error> {
+        self.ask(Cmd::Mode).await
+    }
+    pub fn set_mode(&self, mode: Mode) -> Result<(), Error> {
+        self.send(Cmd::SetMode(mode))
     }
-    pub fn queue(&self) -> Queue {
-        self.cmd.send(Cmd::Queue).unwrap();
-        match self.rpl.recv().unwrap() {
-            Rpl::Queue(que) => que,
-            _ => unreachable!(),
+    /// Brings back a saved queue, starting at the saved position, paused unless `resume` is set.
+    pub fn restore(&self, songs: Vec<Song>, state: State, resume: bool) -> Result<(), Error> {
+        let queue = Queue::restore(songs, state.index, state.order, state.mode);
+        self.send(Cmd::Restore(
+            queue,
+            Duration::from_millis(state.position_ms),
+            state.pause || !resume,
+        ))
+    }
+    pub async fn eq(&self) -> Result<Eq, Error> {
+        self.ask(Cmd::Eq).await
+    }
+    pub fn set_eq(&self, eq: Eq) -> Result<(), Error> {
+        self.send(Cmd::SetEq(eq))
+    }
+}
+
+/// Opens `song` for the speaker, its decode errors are reported back to the player thread.
+/// `skipped` tells whether playback moves on when it can not be opened.
+fn open(
+    song: &Song,
+    speaker: &Speaker,
+    conf: &Config,
+    cmd: &Sender<Cmd>,
+    skipped: bool,
+) -> Option<Source> {
+    let song_id = song.song_id;
+    let report = cmd.clone();
+    let on_error = Box::new(move |error, skipped| {
+        let _ = report.send(Cmd::Failed(song_id, error, skipped));
+    });
+    let src = File::open(&song.file)
+        .map_err(|err| err.to_string())
+        .and_then(|file| {
+            Source::new(
+                file,
+                speaker.channels(),
+                speaker.sample_rate(),
+                conf.mono,
+                Duration::from_millis(conf.lookahead_ms),
+                conf.max_decode_errors,
+                on_error,
+            )
+            .map_err(|err| err.to_string())
+        });
+    match src {
+        Ok(src) => Some(src),
+        Err(err) => {
+            eprintln!("could not open {}: {err}", song.file);
+            let _ = cmd.send(Cmd::Failed(song_id, err, skipped));
+            None
         }
     }
-    pub fn now(&self) -> Option<(u32, String)> {
-        self.cmd.send(Cmd::Now).unwrap();
-        match self.rpl.recv().unwrap() {
-            Rpl::Now(now) => now,
-            _ => unreachable!(),
+}
+
+/// Opens the song that follows the current one and hands it to the speaker.
+/// Songs from the same album are never crossfaded.
+fn preload(
+    queue: &Queue,
+    speaker: &Speaker,
+    next: &mut Option<(u32, Arc<Source>)>,
+    crossfade: &Crossfade,
+    replaygain: &ReplayGain,
+    conf: &Config,
+    cmd: &Sender<Cmd>,
+) {
+    let upcoming = queue.peek();
+    match (&upcoming, &next) {
+        (Some(song), Some((id, _))) if song.song_id == *id => {}
+        _ => {
+            next.take();
+            if let Some(song) = &upcoming {
+                if let Some(src) = open(song, speaker, conf, cmd, false) {
+                    next.replace((song.song_id, Arc::new(src)));
+                }
+            }
         }
     }
+    if let (Some(song), Some((_, src))) = (&upcoming, &next) {
+        src.set_gain(replaygain.factor(
+            song,
+            queue.upcoming().is_some_and(|index| queue.in_album(index)),
+        ))
+    }
+    let same_album = match (queue.now(), upcoming) {
+        (Some(now), Some(upcoming)) => now.album_id == upcoming.album_id,
+        _ => false,
+    };
+    speaker.set_next(
+        next.as_ref().map(|(_, src)| src.clone()),
+        match crossfade.enabled() && !same_album {
+            true => Some(*crossfade),
+            false => None,
+        },
+    )
+}
+
+fn save(db: &DB, queue: &Queue, source: &Option<Arc<Source>>, latency: Duration) {
+    let (position, pause) = match source {
+        Some(src) => (src.heard(latency), src.paused()),
+        None => (Duration::from_secs(0), false),
+    };
+    let state = State {
+        songs: queue.songs.iter().map(|song| song.song_id).collect(),
+        index: queue.index,
+        order: queue.order.clone(),
+        position_ms: position.as_millis() as u64,
+        pause,
+        mode: queue.mode,
+    };
+    if let Err(err) = state.save(db) {
+        eprintln!("could not save the queue: {err}")
+    }
 }
 
-impl Drop for Player {
+impl Drop for Thread {
     fn drop(&mut self) {
-        self.cmd.send(Cmd::Die).unwrap()
+        let _ = self.cmd.send(Cmd::Die);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, f32::consts::TAU, fs, path::Path, time::Duration};
+
+    use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+    use serde_json::json;
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::database::Gain;
+
+    /// Writes half a second of a 440Hz tone at the default output format.
+    fn tone(path: &Path) {
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 48_000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(path, spec).unwrap();
+        for i in 0..24_000 {
+            let sample = ((i as f32 * 440. / 48_000. * TAU).sin() * 16_000.) as i16;
+            writer.write_sample(sample).unwrap();
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap()
+    }
+
+    #[tokio::test]
+    async fn plays_a_song_to_the_wav_output() {
+        let dir = env::temp_dir().join(format!("yampd-player-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (file, out) = (dir.join("tone.wav"), dir.join("out.wav"));
+        tone(&file);
+        let conf: Config = serde_json::from_value(json!({
+            "db_path": dir.join("yampd.db"),
+            "music": [],
+            "addr": "127.0.0.1:0",
+            "output": { "type": "wav", "path": out },
+        }))
+        .unwrap();
+        let events = bus();
+        let mut received = events.subscribe();
+        let player = Player::new(&conf, events);
+        player
+            .push(Song {
+                song_id: 1,
+                artist_id: 1,
+                album_id: 1,
+                title: "Tone".into(),
+                artist: "Test".into(),
+                album: "Test".into(),
+                ms: 500,
+                inferred: false,
+                file: file.to_str().unwrap().into(),
+                size: 0,
+                gain: Gain::default(),
+            })
+            .unwrap();
+        player.play().unwrap();
+        let ended = timeout(Duration::from_secs(10), async {
+            loop {
+                if let Event::TrackEnded { song_id } = received.recv().await.unwrap() {
+                    return song_id;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(ended, 1);
+        let status = player.status().await.unwrap();
+        assert!(status.song.is_none());
+        // the output file is finished once the player thread and with it the output are gone
+        drop(player);
+        let samples: Vec<f32> = WavReader::open(&out)
+            .unwrap()
+            .into_samples()
+            .map(Result::unwrap)
+            .collect();
+        let loud = samples.iter().filter(|sample| sample.abs() > 0.1).count();
+        assert!(
+            loud > 40_000,
+            "{loud} of {} samples were played",
+            samples.len()
+        );
+        fs::remove_dir_all(dir).unwrap();
     }
 }
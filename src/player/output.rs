@@ -0,0 +1,315 @@
+use std::{
+    fs::File,
+    io::BufWriter,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::{sleep, spawn, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    OutputCallbackInfo, SampleRate, Stream, StreamConfig, StreamError,
+};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use serde::{Deserialize, Serialize};
+
+pub type Render = Box<dyn FnMut(&mut [f32]) + Send>;
+
+pub trait Output {
+    fn channels(&self) -> u8;
+    fn sample_rate(&self) -> u32;
+    /// How long a rendered sample takes to be heard.
+    fn latency(&self) -> Duration;
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Cpal,
+    Null,
+    Wav {
+        path: String,
+    },
+}
+
+impl Backend {
+    /// Identifies the output, e.g. to remember settings per output.
+    pub fn id(&self) -> String {
+        match self {
+            Backend::Cpal => "cpal".into(),
+            Backend::Null => "null".into(),
+            Backend::Wav { path } => format!("wav:{path}"),
+        }
+    }
+    pub fn open(&self, channels: u8, sample_rate: u32, render: Render) -> Box<dyn Output> {
+        match self {
+            Backend::Cpal => {
+                match cpal::default_host().default_output_device() {
+                    Some(device) => match CpalOutput::new(device, channels, sample_rate, render) {
+                        Ok(output) => Box::new(output),
+                        Err((err, render)) => {
+                            eprintln!("could not open the output device, falling back to null output: {err}");
+                            Box::new(NullOutput::new(channels, sample_rate, render))
+                        }
+                    },
+                    None => {
+                        eprintln!("no output device found, falling back to null output");
+                        Box::new(NullOutput::new(channels, sample_rate, render))
+                    }
+                }
+            }
+            Backend::Null => Box::new(NullOutput::new(channels, sample_rate, render)),
+            Backend::Wav { path } => match WavOutput::new(path, channels, sample_rate, render) {
+                Ok(output) => Box::new(output),
+                Err((err, render)) => {
+                    eprintln!("could not create {path}, falling back to null output: {err}");
+                    Box::new(NullOutput::new(channels, sample_rate, render))
+                }
+            },
+        }
+    }
+}
+
+pub struct CpalOutput {
+    _stream: Stream,
+    /// takes over rendering once the device is gone
+    _fallback: Arc<Mutex<Option<Clock>>>,
+    channels: u8,
+    sample_rate: u32,
+    /// nanoseconds from the last callback to the end of the buffer it filled being played
+    latency: Arc<AtomicU64>,
+}
+
+impl CpalOutput {
+    /// Hands `render` back when the stream can not be started, so another output can use it.
+    fn new(
+        device: cpal::Device,
+        channels: u8,
+        sample_rate: u32,
+        render: Render,
+    ) -> Result<CpalOutput, (String, Render)> {
+        // shared with the fallback clock, only one of them renders at a time
+        let render = Arc::new(Mutex::new(render));
+        let failed = Arc::new(AtomicBool::new(false));
+        let fallback = Arc::new(Mutex::new(None));
+        let latency = Arc::new(AtomicU64::new(0));
+        let stream = device.build_output_stream(
+            &StreamConfig {
+                channels: channels as u16,
+                sample_rate: SampleRate(sample_rate),
+                buffer_size: cpal::BufferSize::Default,
+            },
+            {
+                let render = render.clone();
+                let failed = failed.clone();
+                let measured = latency.clone();
+                move |data: &mut [f32], info: &OutputCallbackInfo| {
+                    match (failed.load(Ordering::Relaxed), render.try_lock()) {
+                        (false, Ok(mut render)) => render(data),
+                        _ => return data.fill(0.),
+                    }
+                    let ts = info.timestamp();
+                    let delay = ts.playback.duration_since(&ts.callback).unwrap_or_default();
+                    let frames = (data.len() / channels as usize) as f64;
+                    let buffered = Duration::from_secs_f64(frames / sample_rate as f64);
+                    measured.store((delay + buffered).as_nanos() as u64, Ordering::Relaxed)
+                }
+            },
+            {
+                let render = render.clone();
+                let fallback = fallback.clone();
+                let measured = latency.clone();
+                move |err| {
+                    eprintln!("output error: {err}");
+                    if matches!(err, StreamError::DeviceNotAvailable)
+                        && !failed.swap(true, Ordering::Relaxed)
+                    {
+                        eprintln!("the output device is gone, falling back to null output");
+                        measured.store(0, Ordering::Relaxed);
+                        let clock =
+                            Clock::new(channels, sample_rate, shared(render.clone()), |_| {});
+                        *fallback.lock().unwrap() = Some(clock)
+                    }
+                }
+            },
+            None,
+        );
+        let started =
+            stream
+                .map_err(|err| err.to_string())
+                .and_then(|stream| match stream.play() {
+                    Ok(()) => Ok(stream),
+                    Err(err) => Err(err.to_string()),
+                });
+        match started {
+            Ok(stream) => Ok(CpalOutput {
+                _stream: stream,
+                _fallback: fallback,
+                channels,
+                sample_rate,
+                latency,
+            }),
+            Err(err) => Err((err, shared(render))),
+        }
+    }
+}
+
+/// A render handed to another output, locking is free as the first one no longer uses it.
+fn shared(render: Arc<Mutex<Render>>) -> Render {
+    Box::new(move |data: &mut [f32]| match render.lock() {
+        Ok(mut render) => render(data),
+        Err(_) => data.fill(0.),
+    })
+}
+
+impl Output for CpalOutput {
+    fn channels(&self) -> u8 {
+        self.channels
+    }
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+    fn latency(&self) -> Duration {
+        Duration::from_nanos(self.latency.load(Ordering::Relaxed))
+    }
+}
+
+/// Pulls 10ms chunks from `render` on its own thread, paced to real time.
+struct Clock {
+    alive: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Clock {
+    fn new(
+        channels: u8,
+        sample_rate: u32,
+        mut render: Render,
+        mut sink: impl FnMut(&[f32]) + Send + 'static,
+    ) -> Clock {
+        let alive = Arc::new(AtomicBool::new(true));
+        let running = alive.clone();
+        let thread = spawn(move || {
+            let period = Duration::from_millis(10);
+            let mut buf = vec![0.; (sample_rate / 100) as usize * channels as usize];
+            let start = Instant::now();
+            let mut ticks = 0;
+            while running.load(Ordering::Relaxed) {
+                render(&mut buf);
+                sink(&buf);
+                ticks += 1;
+                let due = start + period * ticks;
+                let now = Instant::now();
+                if due > now {
+                    sleep(due - now)
+                }
+            }
+        });
+        Clock {
+            alive,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Clock {
+    fn drop(&mut self) {
+        self.alive.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+pub struct NullOutput {
+    _clock: Clock,
+    channels: u8,
+    sample_rate: u32,
+}
+
+impl NullOutput {
+    fn new(channels: u8, sample_rate: u32, render: Render) -> NullOutput {
+        NullOutput {
+            _clock: Clock::new(channels, sample_rate, render, |_| {}),
+            channels,
+            sample_rate,
+        }
+    }
+}
+
+impl Output for NullOutput {
+    fn channels(&self) -> u8 {
+        self.channels
+    }
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+    /// the clock hands out samples as they are due
+    fn latency(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+/// Writes the rendered stream to `path`, the file is rewritten whenever the output is reopened.
+pub struct WavOutput {
+    _clock: Clock,
+    channels: u8,
+    sample_rate: u32,
+}
+
+impl WavOutput {
+    /// Hands `render` back when the file can not be created.
+    fn new(
+        path: &str,
+        channels: u8,
+        sample_rate: u32,
+        render: Render,
+    ) -> Result<WavOutput, (hound::Error, Render)> {
+        let created: Result<WavWriter<BufWriter<File>>, _> = WavWriter::create(
+            path,
+            WavSpec {
+                channels: channels as u16,
+                sample_rate,
+                bits_per_sample: 32,
+                sample_format: SampleFormat::Float,
+            },
+        );
+        let mut writer = match created {
+            Ok(writer) => Some(writer),
+            Err(err) => return Err((err, render)),
+        };
+        let path = path.to_owned();
+        // after a failed write the samples are dropped, playback goes on as with the null output
+        let sink = move |buf: &[f32]| {
+            let Some(out) = &mut writer else {
+                return;
+            };
+            if let Err(err) = buf.iter().try_for_each(|sample| out.write_sample(*sample)) {
+                eprintln!("writing {path} failed, dropping the output from now on: {err}");
+                writer = None
+            }
+        };
+        Ok(WavOutput {
+            _clock: Clock::new(channels, sample_rate, render, sink),
+            channels,
+            sample_rate,
+        })
+    }
+}
+
+impl Output for WavOutput {
+    fn channels(&self) -> u8 {
+        self.channels
+    }
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+    /// the clock hands out samples as they are due
+    fn latency(&self) -> Duration {
+        Duration::ZERO
+    }
+}
@@ -1,44 +1,555 @@
+use rand::{seq::SliceRandom, thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
 use crate::database::Song;
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Repeat {
+    #[default]
+    Off,
+    One,
+    All,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, ToSchema)]
+pub struct Mode {
+    pub repeat: Repeat,
+    pub shuffle: bool,
+    /// removes songs from the queue once they finished playing
+    #[serde(default)]
+    pub consume: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct Queue {
     pub index: usize,
-    pub songs: Vec<(u32, String)>,
+    pub songs: Vec<Song>,
+    pub mode: Mode,
+    /// indices into `songs` in the order they are played
+    pub order: Vec<usize>,
 }
 impl Queue {
     pub fn new() -> Queue {
         Queue {
             index: 0,
             songs: vec![],
+            mode: Mode::default(),
+            order: vec![],
+        }
+    }
+    /// Rebuilds a saved queue, falling back to the plain order if it no longer fits the songs.
+    pub fn restore(songs: Vec<Song>, index: usize, mut order: Vec<usize>, mode: Mode) -> Queue {
+        let mut sorted = order.clone();
+        sorted.sort_unstable();
+        if !sorted.into_iter().eq(0..songs.len()) {
+            order = (0..songs.len()).collect()
+        }
+        Queue {
+            index: index.min(songs.len()),
+            songs,
+            mode,
+            order,
         }
     }
     pub fn push(&mut self, song: Song) {
-        self.songs.push((song.song_id, song.file));
+        self.insert(self.songs.len(), vec![song])
+    }
+    /// Inserts `songs` before the entry at `at`, appending when `at` is past the end.
+    pub fn insert(&mut self, at: usize, songs: Vec<Song>) {
+        let at = at.min(self.songs.len());
+        let count = songs.len();
+        // past the end the index stays put, so appended songs are up next
+        if self.index > at || (self.index == at && at < self.songs.len()) {
+            self.index += count
+        }
+        self.songs.splice(at..at, songs);
+        self.order
+            .iter_mut()
+            .filter(|i| **i >= at)
+            .for_each(|i| *i += count);
+        match self.mode.shuffle {
+            true => {
+                for index in at..at + count {
+                    let from = self.position().map_or(0, |pos| pos + 1);
+                    let pos = thread_rng().gen_range(from..=self.order.len());
+                    self.order.insert(pos, index)
+                }
+            }
+            false => self.order = (0..self.songs.len()).collect(),
+        }
+    }
+    /// Inserts `songs` so they are played right after the current song.
+    pub fn play_next(&mut self, songs: Vec<Song>) {
+        let count = songs.len();
+        let at = match self.index < self.songs.len() {
+            true => self.index + 1,
+            false => self.songs.len(),
+        };
+        let stopped = self.index >= self.songs.len();
+        self.insert(at, songs);
+        if stopped {
+            self.index = at
+        }
+        if self.mode.shuffle {
+            self.order.retain(|i| !(at..at + count).contains(i));
+            let pos = match stopped {
+                true => 0,
+                false => self.position().map_or(0, |pos| pos + 1),
+            };
+            self.order.splice(pos..pos, at..at + count);
+        }
+    }
+    /// Moves `count` entries starting at `from` so the first of them ends up at `to`.
+    pub fn move_range(&mut self, from: usize, count: usize, to: usize) {
+        if from >= self.songs.len() {
+            return;
+        }
+        let count = count.min(self.songs.len() - from);
+        let to = to.min(self.songs.len() - count);
+        let mut list: Vec<usize> = (0..self.songs.len()).collect();
+        let moved: Vec<usize> = list.drain(from..from + count).collect();
+        list.splice(to..to, moved);
+        let mut map = vec![0; list.len()];
+        for (new, old) in list.iter().enumerate() {
+            map[*old] = new
+        }
+        self.songs = list.iter().map(|old| self.songs[*old].clone()).collect();
+        if let Some(index) = map.get(self.index) {
+            self.index = *index
+        }
+        match self.mode.shuffle {
+            true => self.order.iter_mut().for_each(|i| *i = map[*i]),
+            false => self.order = (0..self.songs.len()).collect(),
+        }
+    }
+    pub fn replace(&mut self, songs: Vec<Song>) {
+        self.clear();
+        self.insert(0, songs);
+        self.index = self.order.first().copied().unwrap_or(0)
+    }
+    pub fn clear(&mut self) {
+        self.songs.clear();
+        self.order.clear();
+        self.index = 0
+    }
+    /// Drops everything but the current song.
+    pub fn crop(&mut self) {
+        match self.now() {
+            Some(song) => {
+                self.songs = vec![song];
+                self.order = vec![0];
+                self.index = 0
+            }
+            None => self.clear(),
+        }
     }
     pub fn delete(&mut self, index: usize) {
-        if index < self.songs.len() {
-            self.songs.remove(index);
+        if index >= self.songs.len() {
+            return;
+        }
+        let target = match index == self.index {
+            true => self.after(),
+            false => Some(self.index),
+        };
+        self.songs.remove(index);
+        self.order.retain(|i| *i != index);
+        self.order
+            .iter_mut()
+            .filter(|i| **i > index)
+            .for_each(|i| *i -= 1);
+        self.index = match target {
+            Some(target) if target == index => self.songs.len(),
+            Some(target) if target > index => target - 1,
+            Some(target) => target,
+            None => self.songs.len(),
         }
     }
     pub fn index(&mut self, index: usize) {
         self.index = index
     }
     pub fn next(&mut self) {
-        if self.index < self.songs.len() - 1 {
-            self.index += 1
+        if let Some(index) = self.after() {
+            self.index = index
         }
     }
     pub fn prev(&mut self) {
-        if self.index != 0 {
-            self.index -= 1
+        if let Some(index) = self.before() {
+            self.index = index
+        }
+    }
+    /// Moves on after the current song finished playing, past the end of the queue if nothing follows.
+    pub fn ended(&mut self) {
+        match self.mode.repeat {
+            Repeat::One => {}
+            _ if self.mode.consume => self.delete(self.index),
+            _ => self.index = self.after().unwrap_or(self.songs.len()),
+        }
+    }
+    /// Moves past a song that can not be played, past the end of the queue if nothing follows.
+    pub fn skip(&mut self) {
+        self.index = self.after().unwrap_or(self.songs.len())
+    }
+    pub fn set_mode(&mut self, mode: Mode) {
+        if mode.shuffle != self.mode.shuffle {
+            self.order = (0..self.songs.len()).collect();
+            if mode.shuffle {
+                self.order.retain(|i| *i != self.index);
+                self.order.shuffle(&mut thread_rng());
+                if self.index < self.songs.len() {
+                    self.order.insert(0, self.index)
+                }
+            }
+        }
+        self.mode = mode
+    }
+    fn position(&self) -> Option<usize> {
+        self.order.iter().position(|i| *i == self.index)
+    }
+    fn after(&self) -> Option<usize> {
+        let pos = self.position()?;
+        match self.order.get(pos + 1) {
+            Some(index) => Some(*index),
+            None if self.mode.repeat == Repeat::All => self.order.first().copied(),
+            None => None,
+        }
+    }
+    fn before(&self) -> Option<usize> {
+        let Some(pos) = self.position() else {
+            return self.order.last().copied();
+        };
+        match pos {
+            0 if self.mode.repeat == Repeat::All => self.order.last().copied(),
+            0 => None,
+            pos => Some(self.order[pos - 1]),
         }
     }
-    pub fn remove(&mut self) {
-        if self.index < self.songs.len() {
-            self.songs.remove(self.index);
+    /// Index of the song played once the current one ends.
+    pub fn upcoming(&self) -> Option<usize> {
+        match self.mode.repeat {
+            Repeat::One if self.index < self.songs.len() => Some(self.index),
+            _ => self.after(),
         }
     }
-    pub fn now(&self) -> Option<(u32, String)> {
+    /// Whether the song at `index` is played next to another song from its album.
+    pub fn in_album(&self, index: usize) -> bool {
+        let Some(song) = self.songs.get(index) else {
+            return false;
+        };
+        let Some(pos) = self.order.iter().position(|i| *i == index) else {
+            return false;
+        };
+        [pos.checked_sub(1), Some(pos + 1)]
+            .into_iter()
+            .flatten()
+            .filter_map(|pos| self.order.get(pos))
+            .filter_map(|index| self.songs.get(*index))
+            .any(|other| other.album_id == song.album_id)
+    }
+    pub fn peek(&self) -> Option<Song> {
+        self.songs.get(self.upcoming()?).cloned()
+    }
+    pub fn now(&self) -> Option<Song> {
         self.songs.get(self.index).cloned()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Mode, Queue, Repeat};
+    use crate::database::{Gain, Song};
+
+    fn song(id: u32) -> Song {
+        Song {
+            song_id: id,
+            artist_id: 1,
+            album_id: 1,
+            title: format!("Song {id}"),
+            artist: "Artist".into(),
+            album: "Album".into(),
+            ms: 1000,
+            inferred: false,
+            file: format!("/music/{id}.flac"),
+            size: 0,
+            gain: Gain::default(),
+        }
+    }
+
+    /// A queue of songs whose ids are their first positions.
+    fn queue(len: u32, mode: Mode) -> Queue {
+        let mut queue = Queue::new();
+        queue.set_mode(mode);
+        (0..len).for_each(|id| queue.push(song(id)));
+        queue
+    }
+
+    fn mode(repeat: Repeat, shuffle: bool) -> Mode {
+        Mode {
+            repeat,
+            shuffle,
+            consume: false,
+        }
+    }
+
+    /// A shuffled queue of `len` songs playing song `now`.
+    fn queue_shuffled(len: u32, now: usize) -> Queue {
+        let mut queue = queue(len, mode(Repeat::Off, false));
+        queue.index(now);
+        queue.set_mode(mode(Repeat::Off, true));
+        queue
+    }
+
+    fn now(queue: &Queue) -> Option<u32> {
+        queue.now().map(|song| song.song_id)
+    }
+
+    /// Song ids in the order they are played.
+    fn played(queue: &Queue) -> Vec<u32> {
+        assert_consistent(queue);
+        queue
+            .order
+            .iter()
+            .map(|i| queue.songs[*i].song_id)
+            .collect()
+    }
+
+    fn assert_consistent(queue: &Queue) {
+        let mut sorted = queue.order.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..queue.songs.len()).collect::<Vec<_>>());
+        assert!(queue.index <= queue.songs.len());
+    }
+
+    #[test]
+    fn repeat_all_wraps_around() {
+        let mut queue = queue(3, mode(Repeat::All, false));
+        queue.prev();
+        assert_eq!(now(&queue), Some(2));
+        assert_eq!(queue.upcoming(), Some(0));
+        queue.next();
+        assert_eq!(now(&queue), Some(0));
+        queue.next();
+        queue.next();
+        queue.ended();
+        assert_eq!(now(&queue), Some(0));
+    }
+
+    #[test]
+    fn repeat_off_stops_at_the_ends() {
+        let mut queue = queue(3, mode(Repeat::Off, false));
+        queue.prev();
+        assert_eq!(now(&queue), Some(0));
+        queue.index(2);
+        assert_eq!(queue.upcoming(), None);
+        queue.next();
+        assert_eq!(now(&queue), Some(2));
+        queue.ended();
+        assert_eq!(now(&queue), None);
+        // from past the end, previous goes back to the last song
+        queue.prev();
+        assert_eq!(now(&queue), Some(2));
+    }
+
+    #[test]
+    fn repeat_one_stays_until_skipped() {
+        let mut queue = queue(3, mode(Repeat::One, false));
+        queue.index(2);
+        assert_eq!(queue.upcoming(), Some(2));
+        queue.ended();
+        assert_eq!(now(&queue), Some(2));
+        queue.prev();
+        assert_eq!(now(&queue), Some(1));
+        queue.next();
+        queue.next();
+        assert_eq!(now(&queue), Some(2));
+    }
+
+    #[test]
+    fn shuffle_keeps_the_current_song() {
+        let mut queue = queue(20, mode(Repeat::Off, false));
+        queue.index(7);
+        queue.set_mode(mode(Repeat::Off, true));
+        assert_eq!(now(&queue), Some(7));
+        assert_eq!(played(&queue)[0], 7);
+
+        // every song comes up once before the end
+        let mut seen = vec![7];
+        while let Some(next) = queue.upcoming() {
+            queue.next();
+            assert_eq!(queue.index, next);
+            seen.push(now(&queue).unwrap());
+        }
+        assert_eq!(seen, played(&queue));
+        queue.prev();
+        assert_eq!(Some(seen[18]), now(&queue));
+
+        // turning it off keeps the song and goes back to the queue order
+        queue.set_mode(mode(Repeat::All, false));
+        assert_eq!(Some(seen[18]), now(&queue));
+        assert_eq!(played(&queue), (0..20).collect::<Vec<_>>());
+        queue.index(19);
+        assert_eq!(queue.upcoming(), Some(0));
+    }
+
+    #[test]
+    fn shuffle_with_repeat_all_starts_over() {
+        let mut queue = queue(5, mode(Repeat::All, true));
+        queue.set_mode(mode(Repeat::All, false));
+        queue.set_mode(mode(Repeat::All, true));
+        let order = played(&queue);
+        queue.index(queue.order[4]);
+        queue.next();
+        assert_eq!(now(&queue), Some(order[0]));
+        queue.prev();
+        assert_eq!(now(&queue), Some(order[4]));
+    }
+
+    #[test]
+    fn moving_a_range_across_the_current_song() {
+        let mut queue = queue(6, mode(Repeat::Off, false));
+        queue.index(2);
+        queue.move_range(1, 3, 3);
+        assert_eq!(played(&queue), [0, 4, 5, 1, 2, 3]);
+        assert_eq!(now(&queue), Some(2));
+        queue.next();
+        assert_eq!(now(&queue), Some(3));
+
+        // shuffled, the play order stays the same song by song
+        let mut queue = queue_shuffled(8, 5);
+        let order = played(&queue);
+        queue.move_range(4, 3, 0);
+        assert_eq!(played(&queue), order);
+        assert_eq!(now(&queue), Some(5));
+        queue.move_range(0, 99, 99);
+        assert_eq!(played(&queue), order);
+        assert_eq!(now(&queue), Some(5));
+    }
+
+    #[test]
+    fn inserting_before_the_current_song() {
+        let mut queue = queue(5, mode(Repeat::Off, false));
+        queue.index(3);
+        queue.insert(1, vec![song(10), song(11)]);
+        assert_eq!(played(&queue), [0, 10, 11, 1, 2, 3, 4]);
+        assert_eq!(now(&queue), Some(3));
+
+        // shuffled, new songs only come up after the current one
+        let mut queue = queue_shuffled(8, 3);
+        let before = played(&queue);
+        queue.insert(0, vec![song(10), song(11)]);
+        assert_eq!(now(&queue), Some(3));
+        let order = played(&queue);
+        let pos = order.iter().position(|id| *id == 3).unwrap();
+        assert!(order[pos + 1..].contains(&10) && order[pos + 1..].contains(&11));
+        let old: Vec<u32> = order.into_iter().filter(|id| *id < 10).collect();
+        assert_eq!(old, before);
+    }
+
+    #[test]
+    fn appending_after_the_end_plays_the_new_songs_next() {
+        let mut queue = queue(2, mode(Repeat::Off, false));
+        queue.index(1);
+        queue.ended();
+        assert_eq!(now(&queue), None);
+        queue.push(song(10));
+        assert_eq!(now(&queue), Some(10));
+    }
+
+    #[test]
+    fn play_next_goes_right_after_the_current_song() {
+        let mut queue = queue(4, mode(Repeat::Off, false));
+        queue.index(1);
+        queue.play_next(vec![song(10), song(11)]);
+        assert_eq!(played(&queue), [0, 1, 10, 11, 2, 3]);
+        assert_eq!(now(&queue), Some(1));
+
+        let mut queue = queue_shuffled(8, 6);
+        queue.play_next(vec![song(10), song(11)]);
+        let order = played(&queue);
+        let pos = order.iter().position(|id| *id == 6).unwrap();
+        assert_eq!(order[pos + 1..pos + 3], [10, 11]);
+        assert_eq!(now(&queue), Some(6));
+    }
+
+    #[test]
+    fn cropping_while_shuffled() {
+        let mut queue = queue_shuffled(8, 5);
+        queue.crop();
+        assert_eq!(played(&queue), [5]);
+        assert_eq!(now(&queue), Some(5));
+        queue.ended();
+        assert_eq!(now(&queue), None);
+        queue.crop();
+        assert!(queue.songs.is_empty());
+    }
+
+    #[test]
+    fn deleting_around_the_current_song() {
+        let mut queue = queue_shuffled(8, 5);
+        let order = played(&queue);
+        let first = order.iter().copied().find(|id| *id != 5).unwrap();
+        queue.delete(first as usize);
+        assert_eq!(now(&queue), Some(5));
+        let upcoming = queue.peek().unwrap().song_id;
+        queue.delete(queue.index);
+        assert_eq!(now(&queue), Some(upcoming));
+        let left: Vec<u32> = order
+            .into_iter()
+            .filter(|id| ![first, 5].contains(id))
+            .collect();
+        assert_eq!(played(&queue), left);
+    }
+
+    #[test]
+    fn finished_songs_stay_without_consume() {
+        let mut queue = queue(3, mode(Repeat::Off, false));
+        queue.ended();
+        queue.ended();
+        assert_eq!(queue.songs.len(), 3);
+        assert_eq!(now(&queue), Some(2));
+    }
+
+    #[test]
+    fn consume_removes_finished_songs() {
+        let consume = Mode {
+            consume: true,
+            ..mode(Repeat::Off, false)
+        };
+        let mut queue = queue(4, consume);
+        queue.index(1);
+        queue.ended();
+        assert_eq!(played(&queue), [0, 2, 3]);
+        assert_eq!(queue.index, 1);
+        assert_eq!(now(&queue), Some(2));
+        queue.ended();
+        queue.ended();
+        assert_eq!(played(&queue), [0]);
+        assert_eq!(now(&queue), None);
+
+        // shuffled, the rest keeps its play order
+        let mut queue = queue_shuffled(6, 2);
+        queue.set_mode(Mode {
+            consume: true,
+            ..mode(Repeat::Off, true)
+        });
+        let order = played(&queue);
+        queue.ended();
+        let left: Vec<u32> = order.iter().copied().filter(|id| *id != 2).collect();
+        assert_eq!(played(&queue), left);
+        let pos = order.iter().position(|id| *id == 2).unwrap();
+        assert_eq!(now(&queue), order.get(pos + 1).copied());
+    }
+
+    #[test]
+    fn consume_keeps_a_repeated_song() {
+        let consume = Mode {
+            consume: true,
+            ..mode(Repeat::One, false)
+        };
+        let mut queue = queue(3, consume);
+        queue.ended();
+        assert_eq!(played(&queue), [0, 1, 2]);
+        assert_eq!(now(&queue), Some(0));
+    }
+}
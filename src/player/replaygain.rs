@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::database::Song;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum GainMode {
+    #[default]
+    Off,
+    Track,
+    Album,
+    /// album gain while the neighbouring queue entries come from the same album
+    Auto,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, ToSchema)]
+pub struct ReplayGain {
+    pub mode: GainMode,
+    /// dB added on top of the stored gain
+    pub preamp: f32,
+}
+
+impl ReplayGain {
+    /// Returns the factor to scale `song` samples by, never pushing its peak above full scale.
+    pub fn factor(&self, song: &Song, in_album: bool) -> f32 {
+        let gain = &song.gain;
+        let album = match self.mode {
+            GainMode::Off => return 1.,
+            GainMode::Track => false,
+            GainMode::Album => true,
+            GainMode::Auto => in_album,
+        };
+        let (db, peak) = match album {
+            true => (
+                gain.album_gain.or(gain.track_gain),
+                gain.album_peak.or(gain.track_peak),
+            ),
+            false => (
+                gain.track_gain.or(gain.album_gain),
+                gain.track_peak.or(gain.album_peak),
+            ),
+        };
+        let Some(db) = db else {
+            return 1.;
+        };
+        let factor = 10f32.powf((db + self.preamp) / 20.);
+        match peak {
+            Some(peak) if peak > 0. => factor.min(1. / peak),
+            _ => factor,
+        }
+    }
+}
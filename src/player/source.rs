@@ -1,166 +1,629 @@
-use std::{fs::File, time::Duration};
+use std::{
+    fs::File,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError},
+        Arc, Mutex,
+    },
+    thread::spawn,
+    time::Duration,
+};
 
+use rtrb::{Consumer, Producer, RingBuffer};
 use symphonia::{
     core::{
-        audio::SampleBuffer,
+        audio::{Channels, SampleBuffer},
         codecs::Decoder,
-        formats::{FormatReader, Packet, SeekMode, SeekTo},
-        io::MediaSourceStream,
-        units::{Time, TimeBase},
+        errors::Error,
+        formats::{FormatReader, SeekMode, SeekTo},
+        io::{MediaSource, MediaSourceStream},
+        units::{Time, TimeBase, TimeStamp},
     },
     default::{get_codecs, get_probe},
 };
 
+use super::convert::Converter;
+
+/// How long the decode thread sleeps while the ring is full.
+const IDLE: Duration = Duration::from_millis(5);
+/// How long opening or seeking waits for the decode thread to hand over a ring.
+const READY: Duration = Duration::from_secs(1);
+
+/// Called from the decode thread with every packet that failed to decode,
+/// `true` once the file is given up on.
+pub type OnError = Box<dyn Fn(String, bool) + Send>;
+
+/// Asks the decode thread to continue at a position, it answers with a ring starting there.
+type Seek = (Duration, Sender<Ring>);
+
+/// A ring with the samples decoded from `pos` on.
+struct Ring {
+    samples: Consumer<f32>,
+    pos: Duration,
+}
+
+/// A ring still on its way from the decode thread. For the first ring this holds the producer
+/// of the empty ring played until then, kept so that ring is not taken for the end of the file.
+struct Pending {
+    ring: Receiver<Ring>,
+    _stand_in: Option<Producer<f32>>,
+}
+
+/// State the output reads without locking.
+struct Shared {
+    pause: AtomicBool,
+    /// bits of the f32 gain
+    gain: AtomicU32,
+    /// samples taken from the current ring
+    read: AtomicU64,
+    /// position of the first sample in the current ring, in nanoseconds
+    base: AtomicU64,
+    /// the current ring ran dry after the decoder reached the end of the file
+    end: AtomicBool,
+    /// the decode thread dropped the current ring for the one a seek asked for, running dry is not the end
+    seeking: AtomicBool,
+}
+
+/// A file decoded ahead of time on its own thread, the output only copies samples out of a ring buffer.
 pub struct Source {
-    fmt: Box<dyn FormatReader>,
-    dec: Box<dyn Decoder>,
+    shared: Arc<Shared>,
+    /// swapped on seeking, the output never waits for it and plays silence instead
+    ring: Mutex<Consumer<f32>>,
+    /// set when the decode thread was too slow to hand over the first ring or the one after a seek
+    pending: Mutex<Option<Pending>>,
+    seek: Sender<Seek>,
+    dur: Duration,
     channels: u8,
     sample_rate: u32,
-    dur: Duration,
-    pause: bool,
-    end: bool,
-    ts: u64,
-    buf: Vec<f32>,
+}
+
+/// The part of a source that lives on the decode thread.
+struct Reader {
+    fmt: Box<dyn FormatReader>,
+    dec: Box<dyn Decoder>,
     tb: TimeBase,
+    rate: u32,
+    /// samples before this timestamp are decoded and thrown away, so seeks land on the exact frame
+    until: TimeStamp,
+    conv: Option<Converter>,
+    /// corrupt packets skipped before giving up on the file
+    budget: u32,
+    errors: u32,
+    on_error: OnError,
 }
-impl Source {
-    pub fn new(file: File) -> Result<Source, symphonia::core::errors::Error> {
-        let fmt = get_probe()
-            .format(
-                &Default::default(),
-                MediaSourceStream::new(Box::new(file), Default::default()),
-                &Default::default(),
-                &Default::default(),
-            )?
-            .format;
-        let dec = get_codecs().make(
-            &fmt.default_track().unwrap().codec_params,
+
+/// A file with its decoder set up, nothing decoded yet.
+struct Probed {
+    fmt: Box<dyn FormatReader>,
+    dec: Box<dyn Decoder>,
+    tb: TimeBase,
+    dur: Duration,
+    layout: Channels,
+    rate: u32,
+}
+
+fn probe(file: Box<dyn MediaSource>) -> Result<Probed, Error> {
+    let mut fmt = get_probe()
+        .format(
+            &Default::default(),
+            MediaSourceStream::new(file, Default::default()),
             &Default::default(),
-        )?;
-        let tb = fmt.default_track().unwrap().codec_params.time_base.unwrap();
-        let dur = tb.calc_time(fmt.default_track().unwrap().codec_params.n_frames.unwrap());
-        let dur = Duration::from_secs_f64(dur.seconds as f64 + dur.frac);
-        let channels = fmt
-            .default_track()
-            .unwrap()
-            .codec_params
-            .channels
-            .unwrap()
-            .count() as u8;
-        let sample_rate = fmt
-            .default_track()
-            .unwrap()
-            .codec_params
-            .sample_rate
-            .unwrap();
+            &Default::default(),
+        )?
+        .format;
+    let track = fmt
+        .default_track()
+        .ok_or(Error::Unsupported("no audio track"))?;
+    let (track_id, params) = (track.id, track.codec_params.clone());
+    let dec = get_codecs().make(&params, &Default::default())?;
+    let rate = params
+        .sample_rate
+        .ok_or(Error::Unsupported("unknown sample rate"))?;
+    let layout = params
+        .channels
+        .ok_or(Error::Unsupported("unknown channel layout"))?;
+    let tb = params.time_base.unwrap_or_else(|| TimeBase::new(1, rate));
+    // headers without a frame count, e.g. MP3s without a Xing header, mean walking the packets once
+    let frames = match params.n_frames {
+        Some(frames) => frames,
+        None => count_frames(&mut fmt, track_id)?,
+    };
+    let dur = tb.calc_time(frames);
+    let dur = Duration::from_secs_f64(dur.seconds as f64 + dur.frac);
+    Ok(Probed {
+        fmt,
+        dec,
+        tb,
+        dur,
+        layout,
+        rate,
+    })
+}
 
-        Ok(Source {
+/// Reads every packet of `track` without decoding it, then goes back to the start.
+fn count_frames(fmt: &mut Box<dyn FormatReader>, track: u32) -> Result<TimeStamp, Error> {
+    let mut end = 0;
+    while let Ok(packet) = fmt.next_packet() {
+        if packet.track_id() == track {
+            end = end.max(packet.ts() + packet.dur())
+        }
+    }
+    fmt.seek(
+        SeekMode::Accurate,
+        SeekTo::TimeStamp {
+            ts: 0,
+            track_id: track,
+        },
+    )?;
+    Ok(end)
+}
+
+/// Length of `file`, read from its headers without decoding it.
+pub fn duration(file: File) -> Result<Duration, Error> {
+    Ok(probe(Box::new(file))?.dur)
+}
+
+impl Source {
+    /// Starts decoding `file` into samples with the given format, keeping up to `ahead` of it buffered.
+    /// `mono` mixes every channel down to one signal before spreading it over the output.
+    /// Corrupt packets are skipped, after more than `budget` of them the file is cut short.
+    pub fn new(
+        file: impl MediaSource + 'static,
+        channels: u8,
+        sample_rate: u32,
+        mono: bool,
+        ahead: Duration,
+        budget: u32,
+        on_error: OnError,
+    ) -> Result<Source, Error> {
+        let Probed {
             fmt,
             dec,
+            tb,
+            dur,
+            layout,
+            rate,
+        } = probe(Box::new(file))?;
+        let same = channels as usize == layout.count() && sample_rate == rate;
+        let conv = match same && (!mono || channels == 1) {
+            true => None,
+            false => Some(
+                Converter::new(layout, rate, channels, sample_rate, mono)
+                    .map_err(|_| Error::Unsupported("sample rate can not be converted"))?,
+            ),
+        };
+        let reader = Reader {
+            fmt,
+            dec,
+            tb,
+            rate,
+            until: 0,
+            conv,
+            budget,
+            errors: 0,
+            on_error,
+        };
+        let capacity = (ahead.as_secs_f64() * sample_rate as f64) as usize;
+        let capacity = capacity.max(sample_rate as usize / 10) * channels as usize;
+        let (seek, seeks) = channel();
+        let (ready, ring) = channel();
+        spawn(move || reader.run(capacity, seeks, ready));
+        let (ring, pending) = match ring.recv_timeout(READY) {
+            Ok(ring) => (ring, None),
+            Err(_) => {
+                let (stand_in, samples) = RingBuffer::new(1);
+                let samples = Ring {
+                    samples,
+                    pos: Duration::ZERO,
+                };
+                let pending = Pending {
+                    ring,
+                    _stand_in: Some(stand_in),
+                };
+                (samples, Some(pending))
+            }
+        };
+        Ok(Source {
+            shared: Arc::new(Shared {
+                pause: AtomicBool::new(false),
+                gain: AtomicU32::new(1f32.to_bits()),
+                read: AtomicU64::new(0),
+                base: AtomicU64::new(ring.pos.as_nanos() as u64),
+                end: AtomicBool::new(false),
+                seeking: AtomicBool::new(false),
+            }),
+            ring: Mutex::new(ring.samples),
+            pending: Mutex::new(pending),
+            seek,
             dur,
-            pause: false,
-            end: false,
-            ts: 0,
-            buf: vec![],
             channels,
             sample_rate,
-            tb,
         })
     }
-    pub fn channels(&self) -> u8 {
-        self.channels
-    }
-    pub fn sample_rate(&self) -> u32 {
-        self.sample_rate
-    }
     pub fn duration(&self) -> Duration {
         self.dur
     }
+    /// Position of the next sample handed to the output.
     pub fn position(&self) -> Duration {
-        let pos = self.tb.calc_time(self.ts);
-        Duration::from_secs_f64(pos.seconds as f64 + pos.frac)
-    }
-    pub fn set_position(&mut self, mut pos: Duration) {
-        self.buf.clear();
-        if pos > self.duration() {
-            pos = self.duration()
-        }
-        let secs = (pos.as_millis() / 1_000) as u64;
-        let mut frac = (pos.as_millis() % 1_000) as f64;
-        while frac > 0. {
-            frac /= 10.;
-        }
-        if secs == 0 && frac < 0.1 {
-            frac = 0.1
-        }
-        self.fmt
-            .seek(
-                SeekMode::Coarse,
-                SeekTo::Time {
-                    time: Time {
-                        seconds: secs,
-                        frac: frac,
-                    },
-                    track_id: None,
-                },
-            )
-            .unwrap();
-        self.next()
+        let base = Duration::from_nanos(self.shared.base.load(Ordering::Relaxed));
+        let frames = self.shared.read.load(Ordering::Relaxed) / self.channels as u64;
+        (base + Duration::from_secs_f64(frames as f64 / self.sample_rate as f64)).min(self.dur)
+    }
+    /// Position of the sample being heard, given how long the output takes to play what it was handed.
+    pub fn heard(&self, latency: Duration) -> Duration {
+        let base = Duration::from_nanos(self.shared.base.load(Ordering::Relaxed));
+        self.position().saturating_sub(latency).max(base)
+    }
+    pub fn remaining(&self) -> Duration {
+        self.duration().saturating_sub(self.position())
+    }
+    /// Waits for the decode thread to seek, then switches the output over to the samples from there.
+    /// A slow seek is finished by the output, which keeps playing the old ring until then.
+    pub fn set_position(&self, pos: Duration) {
+        let (ready, ring) = channel();
+        self.shared.seeking.store(true, Ordering::Relaxed);
+        if self.seek.send((pos.min(self.dur), ready)).is_err() {
+            return self.shared.seeking.store(false, Ordering::Relaxed);
+        }
+        let ring = match ring.recv_timeout(READY) {
+            Ok(ring) => ring,
+            Err(RecvTimeoutError::Timeout) => {
+                let pending = Pending {
+                    ring,
+                    _stand_in: None,
+                };
+                *self.pending.lock().unwrap() = Some(pending);
+                return;
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return self.shared.seeking.store(false, Ordering::Relaxed)
+            }
+        };
+        let mut samples = self.ring.lock().unwrap();
+        *samples = ring.samples;
+        self.shared.read.store(0, Ordering::Relaxed);
+        self.shared
+            .base
+            .store(ring.pos.as_nanos() as u64, Ordering::Relaxed);
+        self.shared.end.store(false, Ordering::Relaxed);
+        self.shared.seeking.store(false, Ordering::Relaxed);
+        *self.pending.lock().unwrap() = None;
+    }
+    pub fn set_gain(&self, gain: f32) {
+        self.shared.gain.store(gain.to_bits(), Ordering::Relaxed)
     }
     pub fn ended(&self) -> bool {
-        self.end
+        self.shared.end.load(Ordering::Relaxed)
     }
     pub fn paused(&self) -> bool {
-        self.pause
+        self.shared.pause.load(Ordering::Relaxed)
+    }
+    pub fn set_pause(&self, pause: bool) {
+        self.shared.pause.store(pause, Ordering::Relaxed)
     }
-    pub fn set_pause(&mut self, pause: bool) {
-        self.pause = pause
+
+    /// Fills `data` and returns how many samples came from the file,
+    /// less than asked for once it ended or when the decoder fell behind.
+    pub fn stream(&self, data: &mut [f32]) -> usize {
+        if self.paused() {
+            data.fill(0.);
+            return data.len();
+        }
+        let Ok(mut ring) = self.ring.try_lock() else {
+            data.fill(0.);
+            return data.len();
+        };
+        self.take_pending(&mut ring);
+        let len = ring.slots().min(data.len());
+        let chunk = ring.read_chunk(len).unwrap();
+        let (first, second) = chunk.as_slices();
+        data[..first.len()].copy_from_slice(first);
+        data[first.len()..len].copy_from_slice(second);
+        chunk.commit_all();
+        self.shared.read.fetch_add(len as u64, Ordering::Relaxed);
+        let gain = f32::from_bits(self.shared.gain.load(Ordering::Relaxed));
+        if gain != 1. {
+            data[..len].iter_mut().for_each(|sample| *sample *= gain);
+        }
+        data[len..].fill(0.);
+        if ring.is_abandoned() && ring.is_empty() && !self.shared.seeking.load(Ordering::Relaxed) {
+            self.shared.end.store(true, Ordering::Relaxed)
+        }
+        len
     }
-    fn packet(&mut self) -> Option<Packet> {
-        match self.fmt.next_packet() {
-            Ok(packet) => {
-                self.ts = packet.ts();
-                Some(packet)
+    /// Switches over to a pending ring once the decode thread hands it over.
+    fn take_pending(&self, samples: &mut Consumer<f32>) {
+        let Ok(mut pending) = self.pending.try_lock() else {
+            return;
+        };
+        let Some(waiting) = pending.as_ref() else {
+            return;
+        };
+        match waiting.ring.try_recv() {
+            Ok(ring) => {
+                *samples = ring.samples;
+                self.shared.read.store(0, Ordering::Relaxed);
+                self.shared
+                    .base
+                    .store(ring.pos.as_nanos() as u64, Ordering::Relaxed);
+                self.shared.end.store(false, Ordering::Relaxed);
+                self.shared.seeking.store(false, Ordering::Relaxed);
+                *pending = None
             }
-            Err(_) => None,
+            // the seek failed and the old ring plays on, or the decode thread is gone
+            // and dropping the stand-in ends the source
+            Err(TryRecvError::Disconnected) => {
+                self.shared.seeking.store(false, Ordering::Relaxed);
+                *pending = None
+            }
+            Err(TryRecvError::Empty) => {}
         }
     }
-    fn decode(&mut self, packet: Packet) -> Vec<f32> {
-        match self.dec.decode(&packet) {
-            Ok(decoded) => {
-                let mut sample_buf =
-                    SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
-                sample_buf.copy_interleaved_ref(decoded);
-                let samples = sample_buf.samples();
-                return samples.to_owned();
+}
+
+impl Reader {
+    /// Keeps the ring filled until the source is dropped, dropping the producer once the file is done
+    /// so the output can tell the end from the decoder falling behind.
+    fn run(mut self, capacity: usize, seeks: Receiver<Seek>, ready: Sender<Ring>) {
+        let (producer, samples) = RingBuffer::new(capacity);
+        let mut ring = Some(producer);
+        // a new ring is handed over once it holds samples, so playback does not start with an underrun
+        let mut handover = Some((
+            ready,
+            Ring {
+                samples,
+                pos: Duration::ZERO,
+            },
+        ));
+        let mut buf: Vec<f32> = vec![];
+        let mut at = 0;
+        let mut done = false;
+        loop {
+            let seek = match &ring {
+                None => seeks.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                Some(ring) if ring.is_full() => seeks.recv_timeout(IDLE),
+                Some(_) => seeks.try_recv().map_err(|err| match err {
+                    TryRecvError::Empty => RecvTimeoutError::Timeout,
+                    TryRecvError::Disconnected => RecvTimeoutError::Disconnected,
+                }),
+            };
+            match seek {
+                Ok((pos, ready)) => {
+                    // on failure `ready` is dropped and the old ring keeps playing
+                    if let Some(pos) = self.seek(pos) {
+                        let (producer, samples) = RingBuffer::new(capacity);
+                        ring = Some(producer);
+                        handover = Some((ready, Ring { samples, pos }));
+                        buf.clear();
+                        at = 0;
+                        done = false;
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+            let Some(producer) = &mut ring else {
+                continue;
+            };
+            if at == buf.len() && !done {
+                buf.clear();
+                at = 0;
+                done = !self.next(&mut buf);
+            }
+            let len = producer.slots().min(buf.len() - at);
+            if let Ok(chunk) = producer.write_chunk_uninit(len) {
+                chunk.fill_from_iter(buf[at..at + len].iter().copied());
+            }
+            at += len;
+            if done && at == buf.len() {
+                ring = None
+            }
+            if len > 0 || ring.is_none() {
+                if let Some((ready, ring)) = handover.take() {
+                    let _ = ready.send(ring);
+                }
+            }
+        }
+    }
+    /// Appends the samples of the next packet in the output format, false once the file is done.
+    fn next(&mut self, out: &mut Vec<f32>) -> bool {
+        loop {
+            let packet = match self.fmt.next_packet() {
+                Ok(packet) => packet,
+                Err(Error::ResetRequired) => {
+                    self.dec.reset();
+                    continue;
+                }
+                Err(Error::DecodeError(err)) if self.skip(err.into()) => continue,
+                // the end of the file shows up as an io error
+                Err(_) => break,
+            };
+            let late = self.tb.calc_time(self.until.saturating_sub(packet.ts()));
+            let late = ((late.seconds as f64 + late.frac) * self.rate as f64).round() as usize;
+            let err = match self.dec.decode(&packet) {
+                Ok(decoded) if late >= decoded.frames() => continue,
+                Ok(decoded) => {
+                    let channels = decoded.spec().channels.count();
+                    let mut sample_buf =
+                        SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+                    sample_buf.copy_interleaved_ref(decoded);
+                    let samples = &sample_buf.samples()[late * channels..];
+                    let converted = match &mut self.conv {
+                        Some(conv) => conv.push(samples, out),
+                        None => {
+                            out.extend_from_slice(samples);
+                            Ok(())
+                        }
+                    };
+                    match converted {
+                        Ok(()) => return true,
+                        Err(err) if self.convert_failed(err.to_string()) => continue,
+                        Err(_) => break,
+                    }
+                }
+                Err(err) => err,
+            };
+            match err {
+                Error::ResetRequired => self.dec.reset(),
+                Error::DecodeError(err) if self.skip(err.into()) => {}
+                Error::IoError(err) if self.skip(err.to_string()) => {}
+                Error::DecodeError(_) | Error::IoError(_) => break,
+                err => {
+                    eprintln!("decoding failed: {err}");
+                    (self.on_error)(err.to_string(), true);
+                    break;
+                }
+            }
+        }
+        self.flush(out);
+        false
+    }
+    /// Reports a corrupt packet, false once the file ran out of its error budget.
+    fn skip(&mut self, err: String) -> bool {
+        self.errors += 1;
+        let give_up = self.errors > self.budget;
+        match give_up {
+            true => eprintln!("giving up after {} corrupt packets: {err}", self.errors),
+            false => eprintln!("skipping a corrupt packet: {err}"),
+        }
+        (self.on_error)(err, give_up);
+        !give_up
+    }
+    /// Counts a chunk the resampler choked on against the error budget and starts it over clean.
+    fn convert_failed(&mut self, err: String) -> bool {
+        if let Some(conv) = &mut self.conv {
+            conv.reset()
+        }
+        self.skip(err)
+    }
+    fn flush(&mut self, out: &mut Vec<f32>) {
+        if let Some(Err(err)) = self.conv.as_mut().map(|conv| conv.flush(out)) {
+            self.convert_failed(err.to_string());
+        }
+    }
+    /// Seeks to `pos` and returns where decoding actually continues.
+    fn seek(&mut self, pos: Duration) -> Option<Duration> {
+        if let Some(conv) = &mut self.conv {
+            conv.reset()
+        }
+        let seeked = self.fmt.seek(
+            SeekMode::Accurate,
+            SeekTo::Time {
+                time: Time::new(pos.as_secs(), pos.subsec_nanos() as f64 / 1e9),
+                track_id: None,
+            },
+        );
+        match seeked {
+            Ok(seeked) => {
+                self.dec.reset();
+                self.until = seeked.required_ts;
+                let time = self.tb.calc_time(seeked.required_ts);
+                Some(Duration::from_secs_f64(time.seconds as f64 + time.frac))
             }
             Err(err) => {
-                panic!("{}", err);
+                eprintln!("seeking failed: {err}");
+                None
             }
         }
     }
+}
 
-    fn next(&mut self) {
-        if let Some(packet) = self.packet() {
-            let mut vec = self.decode(packet);
-            self.buf.append(vec.as_mut())
-        } else {
-            self.end = true
-        }
+#[cfg(test)]
+mod tests {
+    use std::{
+        f32::consts::TAU,
+        io::{self, Cursor, Read, Seek, SeekFrom},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::sleep,
+        time::{Duration, Instant},
+    };
+
+    use hound::{SampleFormat, WavSpec, WavWriter};
+    use symphonia::core::io::MediaSource;
+
+    use super::{Source, READY};
+
+    /// A file in memory that takes longer than `READY` for the first access once `slow` is set.
+    struct Slow {
+        file: Cursor<Vec<u8>>,
+        slow: Arc<AtomicBool>,
     }
 
-    pub fn stream(&mut self, data: &mut [f32]) {
-        for i in data {
-            if !self.pause && !self.end {
-                if self.buf.len() == 0 {
-                    self.next();
-                };
-                if self.buf.len() != 0 {
-                    *i = self.buf.remove(0)
-                }
-            } else {
-                *i = 0.
+    impl Slow {
+        fn wait(&self) {
+            if self.slow.swap(false, Ordering::Relaxed) {
+                sleep(READY + Duration::from_millis(500))
             }
         }
     }
+
+    impl Read for Slow {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.wait();
+            self.file.read(buf)
+        }
+    }
+
+    impl Seek for Slow {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.wait();
+            self.file.seek(pos)
+        }
+    }
+
+    impl MediaSource for Slow {
+        fn is_seekable(&self) -> bool {
+            true
+        }
+        fn byte_len(&self) -> Option<u64> {
+            Some(self.file.get_ref().len() as u64)
+        }
+    }
+
+    fn tone(secs: u32) -> Vec<u8> {
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 48_000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut file = Cursor::new(vec![]);
+        let mut writer = WavWriter::new(&mut file, spec).unwrap();
+        for i in 0..48_000 * secs {
+            let sample = ((i as f32 * 440. / 48_000. * TAU).sin() * 16_000.) as i16;
+            writer.write_sample(sample).unwrap();
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        file.into_inner()
+    }
+
+    #[test]
+    fn a_slow_seek_does_not_end_the_source() {
+        let slow = Arc::new(AtomicBool::new(false));
+        let file = Slow {
+            file: Cursor::new(tone(4)),
+            slow: slow.clone(),
+        };
+        let ahead = Duration::from_millis(100);
+        let source = Source::new(file, 2, 48_000, false, ahead, 0, Box::new(|_, _| {})).unwrap();
+
+        slow.store(true, Ordering::Relaxed);
+        source.set_position(Duration::from_secs(2));
+        let mut data = vec![0.; 960];
+        let start = Instant::now();
+        while source.position() < Duration::from_secs(2) {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "the seek never landed"
+            );
+            source.stream(&mut data);
+            assert!(!source.ended(), "the source ended while seeking");
+            sleep(Duration::from_millis(10))
+        }
+        assert!(source.position() < Duration::from_secs(3));
+    }
 }
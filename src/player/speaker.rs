@@ -1,63 +1,167 @@
-use std::sync::{
-    mpsc::{channel, Sender},
-    Arc, Mutex,
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{channel, Sender},
+        Arc,
+    },
+    time::Duration,
 };
 
-use cpal::{
-    traits::{DeviceTrait, HostTrait, StreamTrait},
-    OutputCallbackInfo, SampleRate, Stream, StreamConfig,
+use super::{
+    crossfade::{Crossfade, Fade},
+    dsp::Chain,
+    output::{Backend, Output},
+    source::Source,
+    volume::Volume,
 };
 
-use super::source::Source;
+enum Cmd {
+    Play(Arc<Source>),
+    Next(Option<Arc<Source>>, Option<Crossfade>),
+    Stop,
+    Volume(Volume),
+    Dsp(Chain),
+}
 
 pub struct Speaker {
-    _stream: Stream,
-    cmd: Sender<Arc<Mutex<Source>>>,
+    output: Box<dyn Output>,
+    cmd: Sender<Cmd>,
+    /// times the decoder could not keep up with the output
+    underruns: Arc<AtomicU64>,
 }
 
 impl Speaker {
-    pub fn new(channels: u8, sample_rate: u32, on_end: Box<dyn Fn() + Send>) -> Speaker {
+    /// `on_end` is called with the queued next source when it took over without a gap, None when playback stopped.
+    pub fn new(
+        backend: &Backend,
+        channels: u8,
+        sample_rate: u32,
+        on_end: Box<dyn Fn(Option<Arc<Source>>) + Send>,
+    ) -> Speaker {
         let (snd, cmd) = channel();
-        let mut src: Option<Arc<Mutex<Source>>> = None;
+        let mut src: Option<Arc<Source>> = None;
+        let mut next: Option<Arc<Source>> = None;
+        let mut crossfade: Option<Crossfade> = None;
+        let mut fade: Option<Fade> = None;
+        let mut mix = vec![];
+        let mut volume = Volume::default();
+        let mut dsp: Chain = vec![];
         let mut on_end_done = true;
-        let stream = cpal::default_host()
-            .default_output_device()
-            .unwrap()
-            .build_output_stream(
-                &StreamConfig {
-                    channels: channels as u16,
-                    sample_rate: SampleRate(sample_rate),
-                    buffer_size: cpal::BufferSize::Default,
-                },
-                move |data: &mut [f32], _: &OutputCallbackInfo| {
-                    if let Ok(rec) = cmd.try_recv() {
-                        src.replace(rec);
-                        on_end_done = false;
+        let underruns = Arc::new(AtomicU64::new(0));
+        let counter = underruns.clone();
+        let output = backend.open(
+            channels,
+            sample_rate,
+            Box::new(move |data: &mut [f32]| {
+                while let Ok(rec) = cmd.try_recv() {
+                    match rec {
+                        Cmd::Play(rec) => {
+                            src.replace(rec);
+                            fade = None;
+                            on_end_done = false;
+                        }
+                        Cmd::Next(rec, rec_crossfade) => {
+                            next = rec;
+                            crossfade = rec_crossfade;
+                        }
+                        Cmd::Stop => {
+                            src.take();
+                        }
+                        Cmd::Volume(rec) => volume = rec,
+                        Cmd::Dsp(rec) => dsp = rec,
+                    }
+                }
+                let mut done = 0;
+                while let Some(cur) = &src {
+                    if let (None, Some(_), Some(crossfade)) = (&fade, &next, &crossfade) {
+                        let left = cur.remaining();
+                        if !cur.paused() && left <= crossfade.duration() {
+                            let frames = left.as_secs_f64() * sample_rate as f64;
+                            fade = Some(Fade::new(crossfade.curve, frames as u64))
+                        }
+                    }
+                    let streamed = cur.stream(&mut data[done..]);
+                    if done + streamed < data.len() && !cur.ended() {
+                        counter.fetch_add(1, Ordering::Relaxed);
+                    }
+                    match (&mut fade, &next) {
+                        (Some(fade), Some(next)) if !cur.paused() => {
+                            mix.resize(data.len() - done, 0.);
+                            next.stream(&mut mix);
+                            let frame = channels as usize;
+                            for (i, (out, inc)) in data[done..]
+                                .chunks_mut(frame)
+                                .zip(mix.chunks(frame))
+                                .enumerate()
+                            {
+                                let (out_gain, inc_gain) = match i * frame < streamed {
+                                    true => fade.step(),
+                                    false => (0., 1.),
+                                };
+                                for (out, inc) in out.iter_mut().zip(inc) {
+                                    *out = *out * out_gain + *inc * inc_gain
+                                }
+                            }
+                            done = data.len()
+                        }
+                        _ => done += streamed,
                     }
-                    if let Some(src) = &src {
-                        let mut lock = src.lock().unwrap();
-                        lock.stream(data);
-                        if !on_end_done && lock.ended() {
-                            on_end();
-                            on_end_done = true
+                    if on_end_done || !cur.ended() {
+                        break;
+                    }
+                    match next.take() {
+                        Some(next) => {
+                            src.replace(next.clone());
+                            fade = None;
+                            on_end(Some(next))
                         }
-                    } else {
-                        for i in data {
-                            *i = 0.
+                        None => {
+                            on_end(None);
+                            on_end_done = true;
+                            break;
                         }
                     }
-                },
-                |err| panic!("{err}"),
-                None,
-            )
-            .unwrap();
-        stream.play().unwrap();
+                }
+                data[done..].fill(0.);
+                for stage in dsp.iter_mut() {
+                    stage.process(data)
+                }
+                volume.apply(data, channels)
+            }),
+        );
         Speaker {
-            _stream: stream,
+            output,
             cmd: snd,
+            underruns,
         }
     }
-    pub fn play(&self, src: Arc<Mutex<Source>>) {
-        self.cmd.send(src).unwrap()
+    pub fn play(&self, src: Arc<Source>) {
+        self.cmd.send(Cmd::Play(src)).unwrap()
+    }
+    pub fn stop(&self) {
+        self.cmd.send(Cmd::Stop).unwrap()
+    }
+    /// Queues the source to continue with once the current one ends,
+    /// mixing the two over the tail of the current one when `crossfade` is set.
+    pub fn set_next(&self, src: Option<Arc<Source>>, crossfade: Option<Crossfade>) {
+        self.cmd.send(Cmd::Next(src, crossfade)).unwrap()
+    }
+    pub fn set_volume(&self, volume: Volume) {
+        self.cmd.send(Cmd::Volume(volume)).unwrap()
+    }
+    pub fn set_dsp(&self, dsp: Chain) {
+        self.cmd.send(Cmd::Dsp(dsp)).unwrap()
+    }
+    pub fn underruns(&self) -> u64 {
+        self.underruns.load(Ordering::Relaxed)
+    }
+    pub fn latency(&self) -> Duration {
+        self.output.latency()
+    }
+    pub fn channels(&self) -> u8 {
+        self.output.channels()
+    }
+    pub fn sample_rate(&self) -> u32 {
+        self.output.sample_rate()
     }
 }
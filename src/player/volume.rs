@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, ToSchema)]
+pub struct Volume {
+    /// 0 to 1
+    pub level: f32,
+    pub mute: bool,
+    /// -1 is fully left, 1 is fully right
+    pub balance: f32,
+}
+
+impl Default for Volume {
+    fn default() -> Self {
+        Volume {
+            level: 1.,
+            mute: false,
+            balance: 0.,
+        }
+    }
+}
+
+impl Volume {
+    /// Brings level and balance into range, values that are not finite fall back to the default.
+    pub fn clamped(self) -> Volume {
+        let default = Volume::default();
+        let finite = |value: f32, default: f32| match value.is_finite() {
+            true => value,
+            false => default,
+        };
+        Volume {
+            level: finite(self.level, default.level).clamp(0., 1.),
+            mute: self.mute,
+            balance: finite(self.balance, default.balance).clamp(-1., 1.),
+        }
+    }
+    pub fn apply(&self, data: &mut [f32], channels: u8) {
+        let level = match self.mute {
+            true => 0.,
+            false => self.level,
+        };
+        let left = level * (1. - self.balance).min(1.);
+        let right = level * (1. + self.balance).min(1.);
+        if left == 1. && right == 1. {
+            return;
+        }
+        for frame in data.chunks_mut(channels as usize) {
+            match frame {
+                [mono] => *mono *= level,
+                [l, r, rest @ ..] => {
+                    *l *= left;
+                    *r *= right;
+                    rest.iter_mut().for_each(|sample| *sample *= level)
+                }
+                [] => {}
+            }
+        }
+    }
+}
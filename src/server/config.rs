@@ -1,36 +1,81 @@
-use std::{fs::File, net::SocketAddr, str::FromStr};
+use std::{fs::File, io, net::SocketAddr, str::FromStr};
 
 use serde::{Deserialize, Serialize};
 
+use crate::player::{Backend, Crossfade, Mode, ReplayGain, Volume};
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
     pub db_path: String,
     pub music: Vec<String>,
+    /// picks up files added to, changed in or removed from `music` while running
+    #[serde(default)]
+    pub watch: bool,
     pub addr: String,
+    #[serde(default)]
+    pub output: Backend,
+    /// every file is converted to this rate and channel count before it reaches the output
+    #[serde(default = "Config::default_sample_rate")]
+    pub sample_rate: u32,
+    #[serde(default = "Config::default_channels")]
+    pub channels: u8,
+    /// mixes every file down to mono
+    #[serde(default)]
+    pub mono: bool,
+    /// how much of a file is decoded ahead of the output
+    #[serde(default = "Config::default_lookahead_ms")]
+    pub lookahead_ms: u64,
+    /// corrupt packets skipped in a file before playback gives up on it and moves on
+    #[serde(default = "Config::default_max_decode_errors")]
+    pub max_decode_errors: u32,
+    #[serde(default)]
+    pub crossfade: Crossfade,
+    #[serde(default)]
+    pub replaygain: ReplayGain,
+    #[serde(default)]
+    pub volume: Volume,
+    #[serde(default)]
+    pub mode: Mode,
+    /// keeps playing the restored queue on startup instead of waiting paused
+    #[serde(default)]
+    pub resume: bool,
+    /// also speaks the MPD protocol on this address when set, e.g. "127.0.0.1:6600"
+    #[serde(default)]
+    pub mpd_addr: Option<String>,
+    /// enables the Subsonic API under /rest for this account
+    #[serde(default)]
+    pub subsonic: Option<Subsonic>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Subsonic {
+    pub user: String,
+    /// kept as is, clients authenticate with a salted hash of it
+    pub password: String,
 }
 
 impl Config {
     pub fn read() -> Config {
-        dirs::config_dir().unwrap().as_path().to_str().unwrap();
-        if let Ok(file) = File::open(format!(
-            "{}/yampd.json",
-            dirs::config_dir().unwrap().as_path().to_str().unwrap()
-        )) {
+        if let Ok(file) = File::open(Config::path()) {
             serde_json::from_reader(file).unwrap()
         } else {
             let conf = Config::default();
-            serde_json::to_writer_pretty(
-                File::create(format!(
-                    "{}/yampd.json",
-                    dirs::config_dir().unwrap().as_path().to_str().unwrap()
-                ))
-                .unwrap(),
-                &conf,
-            )
-            .unwrap();
+            if let Err(err) = conf.write() {
+                eprintln!("could not write {}: {err}", Config::path())
+            }
             conf
         }
     }
+    pub fn write(&self) -> io::Result<()> {
+        serde_json::to_writer_pretty(File::create(Config::path())?, self)?;
+        Ok(())
+    }
+    fn path() -> String {
+        format!(
+            "{}/yampd.json",
+            dirs::config_dir().unwrap().as_path().to_str().unwrap()
+        )
+    }
     fn default() -> Config {
         Config {
             db_path: format!(
@@ -44,9 +89,35 @@ impl Config {
                 .into_iter()
                 .map(|s| s.into())
                 .collect(),
+            watch: false,
             addr: "127.0.0.1:2137".into(),
+            output: Backend::default(),
+            sample_rate: Config::default_sample_rate(),
+            channels: Config::default_channels(),
+            mono: false,
+            lookahead_ms: Config::default_lookahead_ms(),
+            max_decode_errors: Config::default_max_decode_errors(),
+            crossfade: Crossfade::default(),
+            replaygain: ReplayGain::default(),
+            volume: Volume::default(),
+            mode: Mode::default(),
+            resume: false,
+            mpd_addr: None,
+            subsonic: None,
         }
     }
+    fn default_sample_rate() -> u32 {
+        48_000
+    }
+    fn default_channels() -> u8 {
+        2
+    }
+    fn default_lookahead_ms() -> u64 {
+        2_000
+    }
+    fn default_max_decode_errors() -> u32 {
+        10
+    }
     pub fn addr(&self) -> SocketAddr {
         SocketAddr::from_str(&self.addr).unwrap()
     }
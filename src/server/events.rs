@@ -0,0 +1,103 @@
+use std::convert::Infallible;
+
+use axum::{
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    response::{
+        sse::{self, KeepAlive, Sse},
+        IntoResponse,
+    },
+    Extension,
+};
+use tokio::sync::watch;
+use tokio_stream::{
+    wrappers::{BroadcastStream, WatchStream},
+    Stream, StreamExt,
+};
+
+use crate::player::{Event, Events};
+
+#[utoipa::path(
+    get,
+    path = "/events",
+    responses(
+        (status = 200, description = "Stream of Events, as Server-Sent Events or as JSON text messages when upgraded to a WebSocket", body = Event),
+    )
+)]
+pub async fn events(
+    Extension(events): Extension<Events>,
+    Extension(stop): Extension<watch::Receiver<bool>>,
+    ws: Option<WebSocketUpgrade>,
+) -> impl IntoResponse {
+    let stream = subscribe(&events, stop);
+    match ws {
+        Some(ws) => ws
+            .on_upgrade(move |socket| websocket(socket, stream))
+            .into_response(),
+        None => {
+            let stream = stream.map(|event| {
+                Ok::<_, Infallible>(
+                    sse::Event::default()
+                        .event(kind(&event))
+                        .json_data(&event)
+                        .unwrap(),
+                )
+            });
+            Sse::new(stream)
+                .keep_alive(KeepAlive::default())
+                .into_response()
+        }
+    }
+}
+
+/// Events as they come in, ending once the server shuts down so it does not wait on the clients.
+fn subscribe(events: &Events, stop: watch::Receiver<bool>) -> impl Stream<Item = Event> {
+    // a slow client misses some events and keeps going
+    let events = BroadcastStream::new(events.subscribe())
+        .filter_map(|event| event.ok())
+        .map(Some);
+    let stop = WatchStream::new(stop).filter(|stop| *stop).map(|_| None);
+    events
+        .merge(stop)
+        .take_while(|event| event.is_some())
+        .filter_map(|event| event)
+}
+
+async fn websocket(mut socket: WebSocket, stream: impl Stream<Item = Event>) {
+    tokio::pin!(stream);
+    loop {
+        tokio::select! {
+            event = stream.next() => match event {
+                Some(event) => {
+                    let text = serde_json::to_string(&event).unwrap();
+                    if socket.send(Message::Text(text)).await.is_err() {
+                        break;
+                    }
+                }
+                None => {
+                    let _ = socket.send(Message::Close(None)).await;
+                    break;
+                }
+            },
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                _ => {}
+            },
+        }
+    }
+}
+
+/// The SSE event name, same as the `type` field of the JSON.
+fn kind(event: &Event) -> &'static str {
+    match event {
+        Event::TrackStarted { .. } => "track_started",
+        Event::TrackEnded { .. } => "track_ended",
+        Event::Paused { .. } => "paused",
+        Event::Stopped => "stopped",
+        Event::Seeked { .. } => "seeked",
+        Event::QueueChanged { .. } => "queue_changed",
+        Event::VolumeChanged { .. } => "volume_changed",
+        Event::PlaybackError { .. } => "playback_error",
+        Event::LibraryChanged { .. } => "library_changed",
+        Event::Scan { .. } => "scan",
+    }
+}
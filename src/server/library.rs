@@ -1,23 +1,33 @@
 use std::sync::{Arc, Mutex};
 
 use axum::{
-    body::{Body, BoxBody, HttpBody},
+    body::Body,
     extract::Path,
-    http::StatusCode,
+    http::{header::CONTENT_TYPE, StatusCode},
     response::{IntoResponse, Response},
-    routing::{get, post},
+    routing::{delete, get, post},
     Extension, Json, Router,
 };
 use serde::Deserialize;
 use utoipa::ToSchema;
 
-use crate::database::{Album, Cover, Song, DB};
+use crate::{
+    database::{Album, Cover, Format, Playlist, Song, DB},
+    player::{Error, Player},
+};
+
+use super::player::Move;
 
 #[derive(Debug, Deserialize, ToSchema)]
 pub struct Query {
     like: String,
 }
 
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct Name {
+    name: String,
+}
+
 pub fn library() -> Router {
     Router::new()
         .route("/song", post(song_by_title))
@@ -26,6 +36,16 @@ pub fn library() -> Router {
         .route("/album", post(album_by_title))
         .route("/album/:id", get(album_by_id))
         .route("/cover/:id", get(cover_by_id))
+        .route("/playlist", get(playlists).post(playlist_create))
+        .route("/playlist/queue", post(playlist_from_queue))
+        .route("/playlist/:id", get(playlist_songs).delete(playlist_delete))
+        .route("/playlist/:id/rename", post(playlist_rename))
+        .route("/playlist/:id/song/:song", post(playlist_add))
+        .route("/playlist/:id/entry/:pos", delete(playlist_remove))
+        .route("/playlist/:id/move", post(playlist_move))
+        .route("/playlist/:id/append", post(playlist_append))
+        .route("/playlist/:id/replace", post(playlist_replace))
+        .route("/playlist/:id/export/:format", get(playlist_export))
 }
 
 #[utoipa::path(
@@ -134,3 +154,255 @@ pub async fn cover_by_id(
             .unwrap(),
     }
 }
+#[utoipa::path(
+    get,
+    path = "/lib/playlist",
+    responses(
+        (status = 200, description = "Get array of all Playlists", body = [Playlist]),
+    )
+)]
+pub async fn playlists(Extension(db): Extension<Arc<Mutex<DB>>>) -> impl IntoResponse {
+    Json(Playlist::all(&db.lock().unwrap()))
+}
+#[utoipa::path(
+    post,
+    path = "/lib/playlist",
+    request_body = Name,
+    responses(
+        (status = 200, description = "Create an empty Playlist", body = Playlist),
+        (status = 409, description = "Playlist with that name exists")
+    )
+)]
+pub async fn playlist_create(
+    Extension(db): Extension<Arc<Mutex<DB>>>,
+    Json(payload): Json<Name>,
+) -> impl IntoResponse {
+    match Playlist::create(&db.lock().unwrap(), &payload.name) {
+        Ok(playlist) => (StatusCode::OK, Json(playlist)).into_response(),
+        Err(_) => (StatusCode::CONFLICT).into_response(),
+    }
+}
+#[utoipa::path(
+    post,
+    path = "/lib/playlist/queue",
+    request_body = Name,
+    responses(
+        (status = 200, description = "Save the queue as Playlist, replacing one with the same name", body = Playlist),
+        (status = 500, description = "Playlist could not be saved")
+    )
+)]
+pub async fn playlist_from_queue(
+    Extension(db): Extension<Arc<Mutex<DB>>>,
+    Extension(ply): Extension<Player>,
+    Json(payload): Json<Name>,
+) -> Result<Response, Error> {
+    let queue = ply.queue().await?;
+    let db = db.lock().unwrap();
+    let songs: Vec<u32> = queue.songs.iter().map(|song| song.song_id).collect();
+    let saved = match Playlist::by_name(&db, &payload.name) {
+        Some(playlist) => Ok(playlist),
+        None => Playlist::create(&db, &payload.name),
+    }
+    .and_then(|playlist| playlist.set_entries(&db, &songs).map(|_| playlist));
+    Ok(match saved {
+        Ok(playlist) => Json(Playlist::by_id(&db, playlist.playlist_id)).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    })
+}
+#[utoipa::path(
+    get,
+    path = "/lib/playlist/{id}",
+    responses(
+        (status = 200, description = "Get array of Songs in Playlist", body = [Song]),
+        (status = 404, description = "Playlist not found")
+    )
+)]
+pub async fn playlist_songs(
+    Extension(db): Extension<Arc<Mutex<DB>>>,
+    Path(id): Path<u32>,
+) -> impl IntoResponse {
+    let db = db.lock().unwrap();
+    match Playlist::by_id(&db, id) {
+        Some(playlist) => (StatusCode::OK, Json(playlist.songs(&db))).into_response(),
+        None => (StatusCode::NOT_FOUND).into_response(),
+    }
+}
+#[utoipa::path(
+    delete,
+    path = "/lib/playlist/{id}",
+    responses(
+        (status = 200, description = "Playlist deleted"),
+        (status = 404, description = "Playlist not found"),
+        (status = 500, description = "Playlist could not be deleted")
+    )
+)]
+pub async fn playlist_delete(
+    Extension(db): Extension<Arc<Mutex<DB>>>,
+    Path(id): Path<u32>,
+) -> impl IntoResponse {
+    let db = db.lock().unwrap();
+    match Playlist::by_id(&db, id).map(|playlist| playlist.delete(&db)) {
+        Some(Ok(())) => StatusCode::OK,
+        Some(Err(_)) => StatusCode::INTERNAL_SERVER_ERROR,
+        None => StatusCode::NOT_FOUND,
+    }
+}
+#[utoipa::path(
+    post,
+    path = "/lib/playlist/{id}/rename",
+    request_body = Name,
+    responses(
+        (status = 200, description = "Playlist renamed"),
+        (status = 404, description = "Playlist not found"),
+        (status = 409, description = "Playlist with that name exists")
+    )
+)]
+pub async fn playlist_rename(
+    Extension(db): Extension<Arc<Mutex<DB>>>,
+    Path(id): Path<u32>,
+    Json(payload): Json<Name>,
+) -> impl IntoResponse {
+    let db = db.lock().unwrap();
+    match Playlist::by_id(&db, id).map(|playlist| playlist.rename(&db, &payload.name)) {
+        Some(Ok(())) => StatusCode::OK,
+        Some(Err(_)) => StatusCode::CONFLICT,
+        None => StatusCode::NOT_FOUND,
+    }
+}
+#[utoipa::path(
+    post,
+    path = "/lib/playlist/{id}/song/{song}",
+    responses(
+        (status = 200, description = "Song appended to Playlist"),
+        (status = 404, description = "Playlist or Song not found"),
+        (status = 500, description = "Playlist could not be changed")
+    )
+)]
+pub async fn playlist_add(
+    Extension(db): Extension<Arc<Mutex<DB>>>,
+    Path((id, song)): Path<(u32, u32)>,
+) -> impl IntoResponse {
+    let db = db.lock().unwrap();
+    match (Playlist::by_id(&db, id), Song::by_id(&db, song)) {
+        (Some(playlist), Some(song)) => match playlist.add(&db, &[song.song_id]) {
+            Ok(()) => StatusCode::OK,
+            Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        },
+        _ => StatusCode::NOT_FOUND,
+    }
+}
+#[utoipa::path(
+    delete,
+    path = "/lib/playlist/{id}/entry/{pos}",
+    responses(
+        (status = 200, description = "Entry removed from Playlist"),
+        (status = 404, description = "Playlist or entry not found"),
+        (status = 500, description = "Playlist could not be changed")
+    )
+)]
+pub async fn playlist_remove(
+    Extension(db): Extension<Arc<Mutex<DB>>>,
+    Path((id, pos)): Path<(u32, usize)>,
+) -> impl IntoResponse {
+    let db = db.lock().unwrap();
+    match Playlist::by_id(&db, id).map(|playlist| playlist.remove(&db, pos)) {
+        Some(Ok(true)) => StatusCode::OK,
+        Some(Err(_)) => StatusCode::INTERNAL_SERVER_ERROR,
+        _ => StatusCode::NOT_FOUND,
+    }
+}
+#[utoipa::path(
+    post,
+    path = "/lib/playlist/{id}/move",
+    request_body = Move,
+    responses(
+        (status = 200, description = "Entries moved"),
+        (status = 404, description = "Playlist not found"),
+        (status = 500, description = "Playlist could not be changed")
+    )
+)]
+pub async fn playlist_move(
+    Extension(db): Extension<Arc<Mutex<DB>>>,
+    Path(id): Path<u32>,
+    Json(payload): Json<Move>,
+) -> impl IntoResponse {
+    let db = db.lock().unwrap();
+    let count = payload.count.unwrap_or(1);
+    match Playlist::by_id(&db, id)
+        .map(|playlist| playlist.move_range(&db, payload.from, count, payload.to))
+    {
+        Some(Ok(())) => StatusCode::OK,
+        Some(Err(_)) => StatusCode::INTERNAL_SERVER_ERROR,
+        None => StatusCode::NOT_FOUND,
+    }
+}
+#[utoipa::path(
+    post,
+    path = "/lib/playlist/{id}/append",
+    responses(
+        (status = 200, description = "Playlist appended to the queue"),
+        (status = 404, description = "Playlist not found")
+    )
+)]
+pub async fn playlist_append(
+    Extension(db): Extension<Arc<Mutex<DB>>>,
+    Extension(ply): Extension<Player>,
+    Path(id): Path<u32>,
+) -> Result<StatusCode, Error> {
+    let db = db.lock().unwrap();
+    match Playlist::by_id(&db, id) {
+        Some(playlist) => {
+            ply.insert(usize::MAX, playlist.songs(&db))?;
+            Ok(StatusCode::OK)
+        }
+        None => Ok(StatusCode::NOT_FOUND),
+    }
+}
+#[utoipa::path(
+    post,
+    path = "/lib/playlist/{id}/replace",
+    responses(
+        (status = 200, description = "Queue replaced with Playlist"),
+        (status = 404, description = "Playlist not found")
+    )
+)]
+pub async fn playlist_replace(
+    Extension(db): Extension<Arc<Mutex<DB>>>,
+    Extension(ply): Extension<Player>,
+    Path(id): Path<u32>,
+) -> Result<StatusCode, Error> {
+    let db = db.lock().unwrap();
+    match Playlist::by_id(&db, id) {
+        Some(playlist) => {
+            ply.replace(playlist.songs(&db))?;
+            Ok(StatusCode::OK)
+        }
+        None => Ok(StatusCode::NOT_FOUND),
+    }
+}
+#[utoipa::path(
+    get,
+    path = "/lib/playlist/{id}/export/{format}",
+    responses(
+        (status = 200, description = "Playlist as m3u, m3u8, pls or xspf file"),
+        (status = 400, description = "Unknown format"),
+        (status = 404, description = "Playlist not found")
+    )
+)]
+pub async fn playlist_export(
+    Extension(db): Extension<Arc<Mutex<DB>>>,
+    Path((id, format)): Path<(u32, String)>,
+) -> impl IntoResponse {
+    let db = db.lock().unwrap();
+    let Some(format) = Format::from_ext(&format) else {
+        return (StatusCode::BAD_REQUEST).into_response();
+    };
+    match Playlist::by_id(&db, id) {
+        Some(playlist) => (
+            [(CONTENT_TYPE, format.mime())],
+            format.export(&playlist.name, &playlist.songs(&db)),
+        )
+            .into_response(),
+        None => (StatusCode::NOT_FOUND).into_response(),
+    }
+}
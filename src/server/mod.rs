@@ -1,25 +1,48 @@
 mod config;
+mod events;
 mod library;
+mod mpd;
+#[cfg(feature = "mpris")]
+mod mpris;
 mod player;
+mod subsonic;
 use std::sync::{Arc, Mutex};
 
-use axum::{Extension, Router};
+use axum::{routing::get, Extension, Router};
 pub use config::*;
+use notify::RecommendedWatcher;
+use tokio::sync::watch;
 use tower_http::cors::{Any, CorsLayer};
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
-use crate::{database::DB, player::Player};
+use crate::{
+    database::{self, Preset, Song, State, DB},
+    player::{bus, emit, Event, Events, Player},
+};
 
 #[derive(Debug, OpenApi)]
 #[openapi(
     paths(
+        events::events,
         library::song_by_id,
         library::song_by_title,
         library::song_by_album_id,
         library::album_by_title,
         library::album_by_id,
         library::cover_by_id,
+        library::playlists,
+        library::playlist_create,
+        library::playlist_from_queue,
+        library::playlist_songs,
+        library::playlist_delete,
+        library::playlist_rename,
+        library::playlist_add,
+        library::playlist_remove,
+        library::playlist_move,
+        library::playlist_append,
+        library::playlist_replace,
+        library::playlist_export,
         player::play,
         player::pause,
         player::unpause,
@@ -30,17 +53,59 @@ use crate::{database::DB, player::Player};
         player::pos_seek_forw,
         player::pos_seek_back,
         player::pos_set,
+        player::pos_percent,
         player::queue,
         player::queue_song,
         player::queue_album,
-        player::now
+        player::insert_song,
+        player::insert_album,
+        player::next_song,
+        player::next_album,
+        player::queue_move,
+        player::queue_clear,
+        player::queue_crop,
+        player::queue_export,
+        player::now,
+        player::errors,
+        player::mode,
+        player::set_mode,
+        player::crossfade,
+        player::set_crossfade,
+        player::replaygain,
+        player::set_replaygain,
+        player::volume,
+        player::set_volume,
+        player::mute,
+        player::eq,
+        player::set_eq,
+        player::presets,
+        player::save_preset,
+        player::delete_preset,
+        player::select_preset,
+        subsonic::rest
     ),
     components(schemas(
+        crate::player::Event,
         crate::database::Album,
         crate::database::Song,
         library::Query,
+        library::Name,
+        crate::database::Playlist,
         player::Queue,
-        player::Now
+        player::Move,
+        player::Now,
+        crate::player::SongError,
+        crate::player::Mode,
+        crate::player::Repeat,
+        crate::player::Crossfade,
+        crate::player::Curve,
+        crate::player::ReplayGain,
+        crate::player::GainMode,
+        crate::player::Volume,
+        crate::player::Eq,
+        crate::player::Band,
+        crate::player::Filter,
+        crate::database::Preset
     ))
 )]
 struct ApiDoc;
@@ -48,26 +113,155 @@ struct ApiDoc;
 pub struct Server {
     conf: Config,
     router: Router,
+    /// tells long-lived streams to end so shutdown does not wait on them
+    stop: watch::Sender<bool>,
+    events: Events,
+    _watcher: Option<RecommendedWatcher>,
 }
 
 impl Server {
     pub fn new(conf: Config) -> Server {
         let cors = CorsLayer::new().allow_origin(Any);
+        let events = bus();
+        let (stop, stopped) = watch::channel(false);
         let db = DB::open(&conf.db_path).unwrap();
-        conf.music.iter().for_each(|path| db.update(path).unwrap());
+        let player = Player::new(&conf, events.clone());
+        if let Some(preset) = Preset::by_output(&db, &conf.output.id()) {
+            player.set_eq(preset.eq).unwrap()
+        }
+        if let Some(mut state) = State::load(&db) {
+            let mut songs: Vec<Song> = vec![];
+            let map: Vec<Option<usize>> = state
+                .songs
+                .iter()
+                .map(|id| {
+                    let song = Song::by_id(&db, *id)?;
+                    songs.push(song);
+                    Some(songs.len() - 1)
+                })
+                .collect();
+            state.remap(&map);
+            if !songs.is_empty() {
+                player.restore(songs, state, conf.resume).unwrap()
+            }
+        }
+        let db = Arc::new(Mutex::new(db));
+        let changed = {
+            let events = events.clone();
+            move |path: &str| emit(&events, Event::LibraryChanged { path: path.into() })
+        };
+        let watcher = match conf.watch {
+            true => database::watch(db.clone(), &conf.music, changed)
+                .map_err(|err| eprintln!("could not watch the music folders: {err}"))
+                .ok(),
+            false => None,
+        };
+        let shared = Arc::new(Mutex::new(conf.clone()));
+        #[cfg(feature = "mpris")]
+        {
+            let mpris = mpris::serve(player.clone(), shared.clone(), events.clone());
+            tokio::spawn(async move {
+                if let Err(err) = mpris.await {
+                    eprintln!("mpris is not available: {err}")
+                }
+            });
+        }
+        if let Some(addr) = conf.mpd_addr.clone() {
+            let mpd = mpd::serve(
+                addr,
+                player.clone(),
+                db.clone(),
+                shared.clone(),
+                events.clone(),
+            );
+            tokio::spawn(async move {
+                if let Err(err) = mpd.await {
+                    eprintln!("mpd server stopped: {err}")
+                }
+            });
+        }
         let router = Router::new()
             .merge(SwaggerUi::new("/swagger-ui").url("/api-doc/openapi.json", ApiDoc::openapi()))
             .nest("/ply", player::player())
             .nest("/lib", library::library())
-            .layer(Extension(Arc::new(Mutex::new(db))))
-            .layer(Extension(Arc::new(Mutex::new(Player::new()))))
+            .nest("/rest", subsonic::subsonic())
+            .route("/events", get(events::events))
+            .layer(Extension(db))
+            .layer(Extension(player))
+            .layer(Extension(shared))
+            .layer(Extension(events.clone()))
+            .layer(Extension(stopped))
             .layer(cors);
 
-        Server { conf, router }
+        Server {
+            conf,
+            router,
+            stop,
+            events,
+            _watcher: watcher,
+        }
     }
+    /// Serves until shut down. The music folders are scanned once the server listens,
+    /// so clients can follow the progress.
     pub async fn run(self) {
-        let _ = axum::Server::bind(&self.conf.addr())
-            .serve(self.router.into_make_service())
+        let server = axum::Server::bind(&self.conf.addr()).serve(self.router.into_make_service());
+        let (db_path, music, events) = (
+            self.conf.db_path.clone(),
+            self.conf.music.clone(),
+            self.events.clone(),
+        );
+        tokio::task::spawn_blocking(move || scan(&db_path, &music, &events));
+        let _ = server
+            .with_graceful_shutdown(async {
+                shutdown().await;
+                let _ = self.stop.send(true);
+            })
             .await;
     }
 }
+
+/// Brings the library up to date on a connection of its own, so requests are not locked out
+/// of it while it scans.
+fn scan(db_path: &str, music: &[String], events: &Events) {
+    let db = match DB::open(db_path) {
+        Ok(db) => db,
+        Err(err) => return eprintln!("could not open the database to scan: {err}"),
+    };
+    for path in music {
+        let progress = |done, total| {
+            emit(
+                events,
+                Event::Scan {
+                    path: path.clone(),
+                    done,
+                    total,
+                },
+            )
+        };
+        match db.update(path, progress) {
+            Ok(true) => {}
+            Ok(false) => {
+                eprintln!("could not read all of {path}, keeping the songs that were not found")
+            }
+            Err(err) => eprintln!("scanning {path} failed: {err}"),
+        }
+    }
+}
+
+/// Resolves on ctrl-c or SIGTERM so the player gets dropped and saves its state.
+async fn shutdown() {
+    let ctrl_c = async { tokio::signal::ctrl_c().await.unwrap() };
+    #[cfg(unix)]
+    let term = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .unwrap()
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let term = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = term => {},
+    }
+}
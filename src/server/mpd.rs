@@ -0,0 +1,1407 @@
+use std::{
+    collections::BTreeSet,
+    fmt::Write,
+    iter::Peekable,
+    ops::Range,
+    str::{Chars, FromStr},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{tcp::OwnedReadHalf, TcpListener, TcpStream},
+    sync::broadcast::{
+        self,
+        error::{RecvError, TryRecvError},
+    },
+};
+
+use crate::{
+    database::{Playlist, Song, DB},
+    player::{self, Event, Events, GainMode, Player, Repeat},
+};
+
+use super::Config;
+
+const VERSION: &str = "0.23.5";
+
+const ACK_ARG: u8 = 2;
+const ACK_UNKNOWN: u8 = 5;
+const ACK_NO_EXIST: u8 = 50;
+const ACK_SYSTEM: u8 = 52;
+const ACK_EXIST: u8 = 56;
+
+const COMMANDS: &[&str] = &[
+    "add",
+    "addid",
+    "clear",
+    "close",
+    "commands",
+    "consume",
+    "count",
+    "crossfade",
+    "currentsong",
+    "decoders",
+    "delete",
+    "deleteid",
+    "find",
+    "getvol",
+    "idle",
+    "list",
+    "listall",
+    "listallinfo",
+    "listplaylist",
+    "listplaylistinfo",
+    "listplaylists",
+    "load",
+    "lsinfo",
+    "move",
+    "moveid",
+    "next",
+    "noidle",
+    "notcommands",
+    "outputs",
+    "pause",
+    "ping",
+    "play",
+    "playid",
+    "playlistadd",
+    "playlistclear",
+    "playlistdelete",
+    "playlistfind",
+    "playlistid",
+    "playlistinfo",
+    "playlistmove",
+    "playlistsearch",
+    "plchanges",
+    "plchangesposid",
+    "previous",
+    "random",
+    "rename",
+    "repeat",
+    "replay_gain_mode",
+    "replay_gain_status",
+    "rescan",
+    "rm",
+    "save",
+    "search",
+    "seek",
+    "seekcur",
+    "seekid",
+    "setvol",
+    "single",
+    "stats",
+    "status",
+    "stop",
+    "tagtypes",
+    "update",
+    "urlhandlers",
+    "volume",
+];
+
+const TAGS: &[&str] = &["Artist", "AlbumArtist", "Album", "Title"];
+
+const SUBSYSTEMS: &[&str] = &[
+    "database",
+    "update",
+    "stored_playlist",
+    "playlist",
+    "player",
+    "mixer",
+    "output",
+    "options",
+];
+
+/// An error reply, sent as `ACK [code@index] {command} msg`.
+struct Ack {
+    code: u8,
+    msg: String,
+}
+
+fn ack(code: u8, msg: impl Into<String>) -> Ack {
+    Ack {
+        code,
+        msg: msg.into(),
+    }
+}
+
+impl From<rusqlite::Error> for Ack {
+    fn from(err: rusqlite::Error) -> Self {
+        ack(ACK_SYSTEM, err.to_string())
+    }
+}
+
+impl From<io::Error> for Ack {
+    fn from(err: io::Error) -> Self {
+        ack(ACK_SYSTEM, format!("could not save the config: {err}"))
+    }
+}
+
+impl From<player::Error> for Ack {
+    fn from(err: player::Error) -> Self {
+        ack(ACK_SYSTEM, err.to_string())
+    }
+}
+
+impl Ack {
+    fn reply(&self, index: usize, cmd: &str) -> String {
+        format!("ACK [{}@{index}] {{{cmd}}} {}\n", self.code, self.msg)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Contains,
+}
+
+struct Filter {
+    /// lowercase tag name
+    tag: String,
+    op: Op,
+    value: String,
+}
+
+#[derive(Clone)]
+struct Client {
+    ply: Player,
+    db: Arc<Mutex<DB>>,
+    conf: Arc<Mutex<Config>>,
+    events: Events,
+    music: Vec<String>,
+    /// bumped whenever the queue changes, reported as `playlist` in `status`
+    version: Arc<AtomicU32>,
+    started: Instant,
+}
+
+/// Speaks the MPD protocol on `addr` so MPD clients can control yampd.
+pub async fn serve(
+    addr: String,
+    ply: Player,
+    db: Arc<Mutex<DB>>,
+    conf: Arc<Mutex<Config>>,
+    events: Events,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(&addr).await?;
+    let music = conf.lock().unwrap().music.clone();
+    let client = Client {
+        ply,
+        db,
+        conf,
+        events: events.clone(),
+        music,
+        version: Arc::new(AtomicU32::new(1)),
+        started: Instant::now(),
+    };
+    let version = client.version.clone();
+    let mut changes = events.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match changes.recv().await {
+                Ok(Event::QueueChanged { .. }) | Err(RecvError::Lagged(_)) => {
+                    version.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(_) => {}
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let client = client.clone();
+        tokio::spawn(async move {
+            let _ = client.run(stream).await;
+        });
+    }
+}
+
+impl Client {
+    async fn run(self, stream: TcpStream) -> io::Result<()> {
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        let mut events = self.events.subscribe();
+        // subsystems changed since they were last reported by idle
+        let mut pending = BTreeSet::new();
+        write
+            .write_all(format!("OK MPD {VERSION}\n").as_bytes())
+            .await?;
+        // commands collected since command_list_begin, and whether each of them gets a list_OK
+        let mut list: Option<(bool, Vec<Vec<String>>)> = None;
+        while let Some(line) = lines.next_line().await? {
+            let args = match split(&line) {
+                Ok(args) if args.is_empty() => {
+                    write
+                        .write_all(ack(ACK_UNKNOWN, "No command given").reply(0, "").as_bytes())
+                        .await?;
+                    continue;
+                }
+                Ok(args) => args,
+                Err(ack) => {
+                    write.write_all(ack.reply(0, "").as_bytes()).await?;
+                    continue;
+                }
+            };
+            if let Some((_, cmds)) = &mut list {
+                if args[0] != "command_list_end" {
+                    cmds.push(args);
+                    continue;
+                }
+                let (list_ok, cmds) = list.take().unwrap();
+                write
+                    .write_all(self.batch(&cmds, list_ok).await.as_bytes())
+                    .await?;
+                continue;
+            }
+            let reply = match args[0].as_str() {
+                "command_list_begin" | "command_list_ok_begin" => {
+                    list = Some((args[0] == "command_list_ok_begin", vec![]));
+                    continue;
+                }
+                "close" => return Ok(()),
+                "idle" => match self
+                    .idle(&args[1..], &mut pending, &mut events, &mut lines)
+                    .await?
+                {
+                    Some(reply) => reply,
+                    None => return Ok(()),
+                },
+                // only meaningful while idling
+                "noidle" => continue,
+                _ => self.batch(&[args], false).await,
+            };
+            write.write_all(reply.as_bytes()).await?;
+        }
+        Ok(())
+    }
+    /// Runs `cmds` in order, stopping at the first one that fails.
+    async fn batch(&self, cmds: &[Vec<String>], list_ok: bool) -> String {
+        let mut out = String::new();
+        for (i, args) in cmds.iter().enumerate() {
+            match self.command(&args[0], &args[1..]).await {
+                Ok(reply) => {
+                    out += &reply;
+                    if list_ok {
+                        out += "list_OK\n"
+                    }
+                }
+                Err(ack) => return out + &ack.reply(i, &args[0]),
+            }
+        }
+        out + "OK\n"
+    }
+    /// Waits until one of the `filter` subsystems, or any when it is empty, changes or the client sends noidle.
+    /// Changes the filter leaves out stay `pending` for a later idle. Returns None once the client is gone.
+    async fn idle(
+        &self,
+        filter: &[String],
+        pending: &mut BTreeSet<&'static str>,
+        events: &mut broadcast::Receiver<Event>,
+        lines: &mut Lines<BufReader<OwnedReadHalf>>,
+    ) -> io::Result<Option<String>> {
+        let wanted = |subsystem: &&str| filter.is_empty() || filter.iter().any(|f| f == subsystem);
+        loop {
+            match events.try_recv() {
+                Ok(event) => pending.extend(subsystems(&event)),
+                Err(TryRecvError::Lagged(_)) => pending.extend(SUBSYSTEMS),
+                Err(_) => break,
+            }
+        }
+        while !pending.iter().any(wanted) {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => pending.extend(subsystems(&event)),
+                    Err(RecvError::Lagged(_)) => pending.extend(SUBSYSTEMS),
+                    Err(RecvError::Closed) => return Ok(None),
+                },
+                line = lines.next_line() => match line? {
+                    Some(line) if line.trim() == "noidle" => break,
+                    // anything else is a protocol error, MPD hangs up as well
+                    _ => return Ok(None),
+                },
+            }
+        }
+        let mut out = String::new();
+        for subsystem in pending.iter().filter(|subsystem| wanted(subsystem)) {
+            writeln!(out, "changed: {subsystem}").unwrap();
+        }
+        pending.retain(|subsystem| !wanted(subsystem));
+        Ok(Some(out + "OK\n"))
+    }
+    async fn command(&self, cmd: &str, args: &[String]) -> Result<String, Ack> {
+        let mut out = String::new();
+        match cmd {
+            "ping" | "password" | "binarylimit" | "decoders" | "urlhandlers" | "notcommands" => {}
+            "commands" => {
+                for cmd in COMMANDS {
+                    writeln!(out, "command: {cmd}").unwrap();
+                }
+            }
+            "tagtypes" if args.is_empty() => {
+                for tag in TAGS {
+                    writeln!(out, "tagtype: {tag}").unwrap();
+                }
+            }
+            "tagtypes" => {}
+            "outputs" => {
+                writeln!(out, "outputid: 0").unwrap();
+                writeln!(out, "outputname: {}", self.conf.lock().unwrap().output.id()).unwrap();
+                writeln!(out, "outputenabled: 1").unwrap();
+            }
+            "status" => self.status(&mut out).await?,
+            "stats" => self.stats(&mut out),
+            "currentsong" => {
+                let queue = self.ply.queue().await?;
+                if let Some(song) = queue.now() {
+                    self.entry(&mut out, &song, queue.index)
+                }
+            }
+            "play" => match args.first() {
+                Some(_) => {
+                    let index = arg(args, 0)?;
+                    self.play(index).await?
+                }
+                None => self.resume().await?,
+            },
+            "playid" => match args.first() {
+                Some(_) => {
+                    let index = self.find_id(arg(args, 0)?).await?;
+                    self.play(index).await?
+                }
+                None => self.resume().await?,
+            },
+            "pause" => {
+                let pause = match args.first() {
+                    Some(_) => flag(args, 0)?,
+                    None => !self.ply.status().await?.paused,
+                };
+                self.ply.set_pause(pause)?
+            }
+            "stop" => self.ply.stop()?,
+            "next" => self.ply.next()?,
+            "previous" => self.ply.prev()?,
+            "seek" => self.seek(arg(args, 0)?, arg(args, 1)?).await?,
+            "seekid" => {
+                let index = self.find_id(arg(args, 0)?).await?;
+                self.seek(index, arg(args, 1)?).await?
+            }
+            "seekcur" => {
+                let time: String = arg(args, 0)?;
+                let secs: f64 = arg(args, 0)?;
+                let secs = match time.starts_with(['+', '-']) {
+                    true => self.ply.status().await?.position.as_secs_f64() + secs,
+                    false => secs,
+                };
+                self.ply.set_position(position(secs)?)?
+            }
+            "add" => {
+                let songs = self.songs(&arg::<String>(args, 0)?);
+                if songs.is_empty() {
+                    return Err(ack(ACK_NO_EXIST, "No such directory"));
+                }
+                self.ply.insert(usize::MAX, songs)?
+            }
+            "addid" => {
+                let uri: String = arg(args, 0)?;
+                let song = self.file(&uri)?;
+                let at = match args.get(1) {
+                    Some(_) => arg(args, 1)?,
+                    None => usize::MAX,
+                };
+                writeln!(out, "Id: {}", song.song_id).unwrap();
+                self.ply.insert(at, vec![song])?
+            }
+            "delete" => {
+                let len = self.ply.queue().await?.songs.len();
+                for index in range(&arg::<String>(args, 0)?, len)?.rev() {
+                    self.ply.delete(index)?
+                }
+            }
+            "deleteid" => {
+                let index = self.find_id(arg(args, 0)?).await?;
+                self.ply.delete(index)?
+            }
+            "move" => {
+                let len = self.ply.queue().await?.songs.len();
+                let range = range(&arg::<String>(args, 0)?, len)?;
+                self.ply
+                    .move_range(range.start, range.len(), arg(args, 1)?)?
+            }
+            "moveid" => {
+                let index = self.find_id(arg(args, 0)?).await?;
+                self.ply.move_range(index, 1, arg(args, 1)?)?
+            }
+            "clear" => self.ply.clear()?,
+            "playlistinfo" => {
+                let queue = self.ply.queue().await?;
+                let range = match args.first() {
+                    Some(arg) => range(arg, queue.songs.len())?,
+                    None => 0..queue.songs.len(),
+                };
+                for index in range {
+                    self.entry(&mut out, &queue.songs[index], index)
+                }
+            }
+            "playlistid" => {
+                let queue = self.ply.queue().await?;
+                let id: Option<u32> = match args.first() {
+                    Some(_) => Some(arg(args, 0)?),
+                    None => None,
+                };
+                for (index, song) in queue.songs.iter().enumerate() {
+                    if id.is_none() || id == Some(song.song_id) {
+                        self.entry(&mut out, song, index)
+                    }
+                }
+                if id.is_some() && out.is_empty() {
+                    return Err(ack(ACK_NO_EXIST, "No such song"));
+                }
+            }
+            // the queue is not versioned per entry, so any change resends all of it
+            "plchanges" | "plchangesposid" => {
+                let version: u32 = arg(args, 0)?;
+                if version != self.version.load(Ordering::Relaxed) {
+                    let queue = self.ply.queue().await?;
+                    for (index, song) in queue.songs.iter().enumerate() {
+                        match cmd {
+                            "plchanges" => self.entry(&mut out, song, index),
+                            _ => writeln!(out, "cpos: {index}\nId: {}", song.song_id).unwrap(),
+                        }
+                    }
+                }
+            }
+            "playlistfind" | "playlistsearch" => {
+                let fold = cmd == "playlistsearch";
+                let (filters, _) = filters(args, fold)?;
+                let queue = self.ply.queue().await?;
+                for (index, song) in queue.songs.iter().enumerate() {
+                    if self.matches(song, &filters, fold) {
+                        self.entry(&mut out, song, index)
+                    }
+                }
+            }
+            "find" | "search" => {
+                let fold = cmd == "search";
+                let (filters, window) = filters(args, fold)?;
+                let songs: Vec<Song> = self
+                    .songs("")
+                    .into_iter()
+                    .filter(|song| self.matches(song, &filters, fold))
+                    .collect();
+                let window = match window {
+                    Some(window) => {
+                        let window = range(&window, usize::MAX)?;
+                        window.start.min(songs.len())..window.end.min(songs.len())
+                    }
+                    None => 0..songs.len(),
+                };
+                for song in &songs[window] {
+                    self.song(&mut out, song)
+                }
+            }
+            "count" => {
+                let (filters, _) = filters(args, false)?;
+                let songs: Vec<Song> = self
+                    .songs("")
+                    .into_iter()
+                    .filter(|song| self.matches(song, &filters, false))
+                    .collect();
+                writeln!(out, "songs: {}", songs.len()).unwrap();
+                let ms: u64 = songs.iter().map(|song| song.ms as u64).sum();
+                writeln!(out, "playtime: {}", ms / 1000).unwrap();
+            }
+            "list" => {
+                let kind: String = arg(args, 0)?;
+                let tag = kind.to_lowercase();
+                let name = tag_name(&tag).ok_or_else(|| ack(ACK_ARG, "Unknown tag type"))?;
+                let filters = match &args[1..] {
+                    // the old `list album ARTIST` form
+                    [artist] if tag == "album" => vec![Filter {
+                        tag: "artist".into(),
+                        op: Op::Eq,
+                        value: artist.clone(),
+                    }],
+                    rest => filters(rest, false)?.0,
+                };
+                let values: BTreeSet<String> = self
+                    .songs("")
+                    .iter()
+                    .filter(|song| self.matches(song, &filters, false))
+                    .filter_map(|song| self.tag(song, &tag))
+                    .collect();
+                for value in values {
+                    writeln!(out, "{name}: {value}").unwrap();
+                }
+            }
+            "lsinfo" => {
+                let uri = args.first().map_or("", |uri| uri.trim_matches('/'));
+                self.lsinfo(&mut out, uri, false)?
+            }
+            "listall" | "listallinfo" => {
+                let uri = args.first().map_or("", |uri| uri.trim_matches('/'));
+                self.lsinfo(&mut out, uri, true)?
+            }
+            "update" | "rescan" => {
+                let db_path = self.conf.lock().unwrap().db_path.clone();
+                let music = self.music.clone();
+                let events = self.events.clone();
+                tokio::task::spawn_blocking(move || super::scan(&db_path, &music, &events));
+                writeln!(out, "updating_db: 1").unwrap();
+            }
+            "setvol" => {
+                let level: u8 = arg(args, 0)?;
+                self.set_volume(|_| level as f32 / 100.).await?
+            }
+            "volume" => {
+                let change: i8 = arg(args, 0)?;
+                self.set_volume(|level| level + change as f32 / 100.)
+                    .await?
+            }
+            "getvol" => {
+                let volume = self.ply.volume().await?;
+                writeln!(out, "volume: {}", percent(volume.level, volume.mute)).unwrap();
+            }
+            "repeat" | "random" | "single" | "consume" => {
+                let on = flag(args, 0)?;
+                let mut mode = self.ply.mode().await?;
+                // single is repeating one song, it is turned off along with repeat
+                match (cmd, on) {
+                    ("repeat", true) if mode.repeat == Repeat::Off => mode.repeat = Repeat::All,
+                    ("repeat", false) => mode.repeat = Repeat::Off,
+                    ("single", true) => mode.repeat = Repeat::One,
+                    ("single", false) if mode.repeat == Repeat::One => mode.repeat = Repeat::All,
+                    ("random", _) => mode.shuffle = on,
+                    ("consume", _) => mode.consume = on,
+                    _ => {}
+                }
+                self.ply.set_mode(mode)?;
+                let mut conf = self.conf.lock().unwrap();
+                conf.mode = mode;
+                conf.write()?
+            }
+            "crossfade" => {
+                let secs: f32 = arg(args, 0)?;
+                let mut crossfade = self.ply.crossfade().await?;
+                crossfade.secs = secs;
+                let Some(crossfade) = crossfade.checked() else {
+                    return Err(ack(ACK_ARG, "Bad crossfade duration"));
+                };
+                self.ply.set_crossfade(crossfade)?;
+                let mut conf = self.conf.lock().unwrap();
+                conf.crossfade = crossfade;
+                conf.write()?
+            }
+            "replay_gain_mode" => {
+                let mode: String = arg(args, 0)?;
+                let mut replaygain = self.ply.replaygain().await?;
+                replaygain.mode = match mode.as_str() {
+                    "off" => GainMode::Off,
+                    "track" => GainMode::Track,
+                    "album" => GainMode::Album,
+                    "auto" => GainMode::Auto,
+                    _ => return Err(ack(ACK_ARG, "Unrecognized replay gain mode")),
+                };
+                self.ply.set_replaygain(replaygain)?;
+                let mut conf = self.conf.lock().unwrap();
+                conf.replaygain = replaygain;
+                conf.write()?
+            }
+            "replay_gain_status" => {
+                let mode = match self.ply.replaygain().await?.mode {
+                    GainMode::Off => "off",
+                    GainMode::Track => "track",
+                    GainMode::Album => "album",
+                    GainMode::Auto => "auto",
+                };
+                writeln!(out, "replay_gain_mode: {mode}").unwrap();
+            }
+            "listplaylists" => {
+                for playlist in Playlist::all(&self.db.lock().unwrap()) {
+                    writeln!(out, "playlist: {}", playlist.name).unwrap();
+                }
+            }
+            "listplaylist" | "listplaylistinfo" => {
+                let db = self.db.lock().unwrap();
+                for song in self.playlist(&db, args)?.songs(&db) {
+                    match cmd {
+                        "listplaylist" => writeln!(out, "file: {}", self.uri(&song.file)).unwrap(),
+                        _ => self.song(&mut out, &song),
+                    }
+                }
+            }
+            "load" => {
+                let songs = {
+                    let db = self.db.lock().unwrap();
+                    self.playlist(&db, args)?.songs(&db)
+                };
+                let songs = match args.get(1) {
+                    Some(window) => songs[range(window, songs.len())?].to_vec(),
+                    None => songs,
+                };
+                let at = match args.get(2) {
+                    Some(_) => arg(args, 2)?,
+                    None => usize::MAX,
+                };
+                self.ply.insert(at, songs)?
+            }
+            "save" => {
+                let name: String = arg(args, 0)?;
+                let songs: Vec<u32> = self
+                    .ply
+                    .queue()
+                    .await?
+                    .songs
+                    .iter()
+                    .map(|song| song.song_id)
+                    .collect();
+                let db = self.db.lock().unwrap();
+                if Playlist::by_name(&db, &name).is_some() {
+                    return Err(ack(ACK_EXIST, "Playlist already exists"));
+                }
+                Playlist::create(&db, &name)?.set_entries(&db, &songs)?
+            }
+            "rm" => {
+                let db = self.db.lock().unwrap();
+                self.playlist(&db, args)?.delete(&db)?
+            }
+            "rename" => {
+                let db = self.db.lock().unwrap();
+                let to: String = arg(args, 1)?;
+                if Playlist::by_name(&db, &to).is_some() {
+                    return Err(ack(ACK_EXIST, "Playlist already exists"));
+                }
+                self.playlist(&db, args)?.rename(&db, &to)?
+            }
+            "playlistadd" => {
+                let songs: Vec<u32> = self
+                    .songs(&arg::<String>(args, 1)?)
+                    .iter()
+                    .map(|song| song.song_id)
+                    .collect();
+                if songs.is_empty() {
+                    return Err(ack(ACK_NO_EXIST, "No such directory"));
+                }
+                let db = self.db.lock().unwrap();
+                let playlist = match Playlist::by_name(&db, &arg::<String>(args, 0)?) {
+                    Some(playlist) => playlist,
+                    None => Playlist::create(&db, &arg::<String>(args, 0)?)?,
+                };
+                playlist.add(&db, &songs)?
+            }
+            "playlistclear" => {
+                let db = self.db.lock().unwrap();
+                self.playlist(&db, args)?.set_entries(&db, &[])?
+            }
+            "playlistdelete" => {
+                let db = self.db.lock().unwrap();
+                if !self.playlist(&db, args)?.remove(&db, arg(args, 1)?)? {
+                    return Err(ack(ACK_ARG, "Bad song index"));
+                }
+            }
+            "playlistmove" => {
+                let db = self.db.lock().unwrap();
+                let playlist = self.playlist(&db, args)?;
+                playlist.move_range(&db, arg(args, 1)?, 1, arg(args, 2)?)?
+            }
+            _ => return Err(ack(ACK_UNKNOWN, format!("unknown command \"{cmd}\""))),
+        }
+        Ok(out)
+    }
+    async fn status(&self, out: &mut String) -> Result<(), Ack> {
+        let queue = self.ply.queue().await?;
+        let status = self.ply.status().await?;
+        let crossfade = self.ply.crossfade().await?;
+        let (volume, duration) = (status.volume, status.duration);
+        writeln!(out, "volume: {}", percent(volume.level, volume.mute)).unwrap();
+        writeln!(out, "repeat: {}", (queue.mode.repeat != Repeat::Off) as u8).unwrap();
+        writeln!(out, "random: {}", queue.mode.shuffle as u8).unwrap();
+        writeln!(out, "single: {}", (queue.mode.repeat == Repeat::One) as u8).unwrap();
+        writeln!(out, "consume: {}", queue.mode.consume as u8).unwrap();
+        writeln!(out, "playlist: {}", self.version.load(Ordering::Relaxed)).unwrap();
+        writeln!(out, "playlistlength: {}", queue.songs.len()).unwrap();
+        if crossfade.enabled() {
+            writeln!(out, "xfade: {}", crossfade.secs.round()).unwrap();
+        }
+        let state = match status.song {
+            Some(_) if duration.is_zero() => "stop",
+            Some(_) if status.paused => "pause",
+            Some(_) => "play",
+            None => "stop",
+        };
+        writeln!(out, "state: {state}").unwrap();
+        if let Some(song) = &status.song {
+            writeln!(out, "song: {}", status.index).unwrap();
+            writeln!(out, "songid: {}", song.song_id).unwrap();
+        }
+        if state != "stop" {
+            let elapsed = status.position;
+            writeln!(out, "time: {}:{}", elapsed.as_secs(), duration.as_secs()).unwrap();
+            writeln!(out, "elapsed: {:.3}", elapsed.as_secs_f64()).unwrap();
+            writeln!(out, "duration: {:.3}", duration.as_secs_f64()).unwrap();
+        }
+        if let Some(index) = queue.upcoming() {
+            writeln!(out, "nextsong: {index}").unwrap();
+            writeln!(out, "nextsongid: {}", queue.songs[index].song_id).unwrap();
+        }
+        Ok(())
+    }
+    fn stats(&self, out: &mut String) {
+        let songs = self.songs("");
+        let artists: BTreeSet<u32> = songs.iter().map(|song| song.artist_id).collect();
+        let albums: BTreeSet<u32> = songs.iter().map(|song| song.album_id).collect();
+        let ms: u64 = songs.iter().map(|song| song.ms as u64).sum();
+        writeln!(out, "artists: {}", artists.len()).unwrap();
+        writeln!(out, "albums: {}", albums.len()).unwrap();
+        writeln!(out, "songs: {}", songs.len()).unwrap();
+        writeln!(out, "uptime: {}", self.started.elapsed().as_secs()).unwrap();
+        writeln!(out, "db_playtime: {}", ms / 1000).unwrap();
+    }
+    fn lsinfo(&self, out: &mut String, uri: &str, recursive: bool) -> Result<(), Ack> {
+        let songs = self.songs(uri);
+        if songs.is_empty() && !uri.is_empty() {
+            return Err(ack(ACK_NO_EXIST, "No such directory"));
+        }
+        let mut dirs = BTreeSet::new();
+        for song in &songs {
+            let file = self.uri(&song.file);
+            if file == uri {
+                self.song(out, song);
+                continue;
+            }
+            let rest = match uri.is_empty() {
+                true => Some(file.as_str()),
+                false => file
+                    .strip_prefix(uri)
+                    .and_then(|rest| rest.strip_prefix('/')),
+            };
+            // nested music directories can put a song below a different uri
+            let Some(rest) = rest else {
+                continue;
+            };
+            let mut parts: Vec<&str> = rest.split('/').collect();
+            parts.pop();
+            if !recursive {
+                parts.truncate(1)
+            }
+            // a directory shows up before the first song below it
+            for depth in 1..=parts.len() {
+                let dir = parts[..depth].join("/");
+                if dirs.insert(dir.clone()) {
+                    match uri.is_empty() {
+                        true => writeln!(out, "directory: {dir}").unwrap(),
+                        false => writeln!(out, "directory: {uri}/{dir}").unwrap(),
+                    }
+                }
+            }
+            if recursive || parts.is_empty() {
+                self.song(out, song)
+            }
+        }
+        if uri.is_empty() && !recursive {
+            for playlist in Playlist::all(&self.db.lock().unwrap()) {
+                writeln!(out, "playlist: {}", playlist.name).unwrap();
+            }
+        }
+        Ok(())
+    }
+    /// Starts playing `index`, or resumes when it is already playing.
+    async fn play(&self, index: usize) -> Result<(), Ack> {
+        if index >= self.ply.queue().await?.songs.len() {
+            return Err(ack(ACK_ARG, "Bad song index"));
+        }
+        Ok(self.ply.index(index)?)
+    }
+    async fn resume(&self) -> Result<(), Ack> {
+        match self.ply.status().await?.duration.is_zero() {
+            true => self.ply.play()?,
+            false => self.ply.set_pause(false)?,
+        }
+        Ok(())
+    }
+    async fn seek(&self, index: usize, secs: f64) -> Result<(), Ack> {
+        let pos = position(secs)?;
+        let status = self.ply.status().await?;
+        if index >= self.ply.queue().await?.songs.len() {
+            return Err(ack(ACK_ARG, "Bad song index"));
+        }
+        if index != status.index || status.duration.is_zero() {
+            self.ply.index(index)?;
+            // index queues the actual start, waiting on a reply makes sure the seek comes after it
+            self.ply.status().await?;
+        }
+        Ok(self.ply.set_position(pos)?)
+    }
+    async fn set_volume(&self, level: impl Fn(f32) -> f32) -> Result<(), Ack> {
+        let mut volume = self.ply.volume().await?;
+        volume.level = level(volume.level);
+        let volume = volume.clamped();
+        self.ply.set_volume(volume)?;
+        let mut conf = self.conf.lock().unwrap();
+        conf.volume = volume;
+        Ok(conf.write()?)
+    }
+    /// Index of the first queue entry playing the song `id`.
+    async fn find_id(&self, id: u32) -> Result<usize, Ack> {
+        self.ply
+            .queue()
+            .await?
+            .songs
+            .iter()
+            .position(|song| song.song_id == id)
+            .ok_or_else(|| ack(ACK_NO_EXIST, "No such song"))
+    }
+    fn playlist(&self, db: &DB, args: &[String]) -> Result<Playlist, Ack> {
+        Playlist::by_name(db, &arg::<String>(args, 0)?)
+            .ok_or_else(|| ack(ACK_NO_EXIST, "No such playlist"))
+    }
+    /// Songs at or below `uri` in any of the music directories.
+    fn songs(&self, uri: &str) -> Vec<Song> {
+        let db = self.db.lock().unwrap();
+        self.music
+            .iter()
+            .flat_map(|root| Song::by_path(&db, &format!("{}/{uri}", root.trim_end_matches('/'))))
+            .collect()
+    }
+    fn file(&self, uri: &str) -> Result<Song, Ack> {
+        self.songs(uri)
+            .into_iter()
+            .find(|song| self.uri(&song.file) == uri.trim_matches('/'))
+            .ok_or_else(|| ack(ACK_NO_EXIST, "No such song"))
+    }
+    /// Path of `file` relative to its music directory, as MPD clients expect it.
+    fn uri(&self, file: &str) -> String {
+        self.music
+            .iter()
+            .find_map(|root| {
+                file.strip_prefix(root.trim_end_matches('/'))?
+                    .strip_prefix('/')
+            })
+            .unwrap_or(file)
+            .into()
+    }
+    fn tag(&self, song: &Song, tag: &str) -> Option<String> {
+        match tag {
+            "artist" | "albumartist" => Some(song.artist.clone()),
+            "album" => Some(song.album.clone()),
+            "title" => Some(song.title.clone()),
+            "file" => Some(self.uri(&song.file)),
+            _ => None,
+        }
+    }
+    fn matches(&self, song: &Song, filters: &[Filter], fold: bool) -> bool {
+        let case = |s: &str| match fold {
+            true => s.to_lowercase(),
+            false => s.to_string(),
+        };
+        filters.iter().all(|filter| {
+            let value = case(&filter.value);
+            let tags = match filter.tag.as_str() {
+                "any" => ["artist", "album", "title", "file"]
+                    .iter()
+                    .filter_map(|tag| self.tag(song, tag))
+                    .collect(),
+                tag => self.tag(song, tag).into_iter().collect::<Vec<_>>(),
+            };
+            let hit = tags.iter().map(|tag| case(tag)).any(|tag| match filter.op {
+                Op::Eq | Op::Ne => tag == value,
+                Op::Contains => tag.contains(&value),
+            });
+            match filter.op {
+                Op::Ne => !hit,
+                _ => hit,
+            }
+        })
+    }
+    fn song(&self, out: &mut String, song: &Song) {
+        writeln!(out, "file: {}", self.uri(&song.file)).unwrap();
+        writeln!(out, "Artist: {}", song.artist).unwrap();
+        writeln!(out, "Album: {}", song.album).unwrap();
+        writeln!(out, "Title: {}", song.title).unwrap();
+        writeln!(out, "Time: {}", (song.ms + 500) / 1000).unwrap();
+        writeln!(out, "duration: {:.3}", song.ms as f64 / 1000.).unwrap();
+    }
+    /// A song in the queue, queue ids are song ids.
+    fn entry(&self, out: &mut String, song: &Song, index: usize) {
+        self.song(out, song);
+        writeln!(out, "Pos: {index}").unwrap();
+        writeln!(out, "Id: {}", song.song_id).unwrap();
+    }
+}
+
+fn subsystems(event: &Event) -> &'static [&'static str] {
+    match event {
+        Event::TrackStarted { .. }
+        | Event::TrackEnded { .. }
+        | Event::Paused { .. }
+        | Event::Stopped
+        | Event::Seeked { .. } => &["player"],
+        Event::QueueChanged { .. } => &["playlist", "options"],
+        Event::VolumeChanged { .. } => &["mixer"],
+        Event::Scan { done, total, .. } if done == total => &["database", "update"],
+        Event::LibraryChanged { .. } => &["database"],
+        Event::Scan { .. } | Event::PlaybackError { .. } => &[],
+    }
+}
+
+fn percent(level: f32, mute: bool) -> u32 {
+    match mute {
+        true => 0,
+        false => (level * 100.).round() as u32,
+    }
+}
+
+fn tag_name(tag: &str) -> Option<&'static str> {
+    match tag {
+        "file" => Some("file"),
+        tag => TAGS.iter().find(|name| name.to_lowercase() == tag).copied(),
+    }
+}
+
+/// Splits a command line into arguments, separated by whitespace and optionally double quoted.
+fn split(line: &str) -> Result<Vec<String>, Ack> {
+    let mut args = vec![];
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '"' => {
+                let mut arg = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => arg.extend(chars.next()),
+                        Some(c) => arg.push(c),
+                        None => return Err(ack(ACK_ARG, "Missing closing '\"'")),
+                    }
+                }
+                args.push(arg)
+            }
+            c => {
+                let mut arg = String::from(c);
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    arg.push(c)
+                }
+                args.push(arg)
+            }
+        }
+    }
+    Ok(args)
+}
+
+/// A position in a song, rejecting seconds no position can hold.
+fn position(secs: f64) -> Result<Duration, Ack> {
+    let bad = || ack(ACK_ARG, "Bad song position");
+    if !secs.is_finite() {
+        return Err(bad());
+    }
+    Duration::try_from_secs_f64(secs.max(0.)).map_err(|_| bad())
+}
+
+fn arg<T: FromStr>(args: &[String], i: usize) -> Result<T, Ack> {
+    let arg = args
+        .get(i)
+        .ok_or_else(|| ack(ACK_ARG, "wrong number of arguments"))?;
+    arg.parse()
+        .map_err(|_| ack(ACK_ARG, format!("Invalid argument \"{arg}\"")))
+}
+
+fn flag(args: &[String], i: usize) -> Result<bool, Ack> {
+    match arg::<u8>(args, i)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(ack(ACK_ARG, "Boolean (0/1) expected")),
+    }
+}
+
+/// Parses `START:END` or a single position, END may be left out to reach `len`.
+fn range(arg: &str, len: usize) -> Result<Range<usize>, Ack> {
+    let bad = || ack(ACK_ARG, "Bad song index");
+    let range = match arg.split_once(':') {
+        Some((start, "")) => start.parse().map_err(|_| bad())?..len,
+        Some((start, end)) => {
+            start.parse().map_err(|_| bad())?..end.parse::<usize>().map_err(|_| bad())?.min(len)
+        }
+        None => {
+            let pos: usize = arg.parse().map_err(|_| bad())?;
+            pos..pos + 1
+        }
+    };
+    match range.start < range.end && range.end <= len {
+        true => Ok(range),
+        false => Err(bad()),
+    }
+}
+
+/// Filters given either as `TAG VALUE` pairs or as `(TAG == 'VALUE')` expressions, along with the `window` if any.
+fn filters(args: &[String], fold: bool) -> Result<(Vec<Filter>, Option<String>), Ack> {
+    let mut filters = vec![];
+    let mut window = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg.starts_with('(') {
+            expression(&mut arg.chars().peekable(), &mut filters)?;
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| ack(ACK_ARG, "Incorrect number of filter arguments"))?;
+        match arg.to_lowercase().as_str() {
+            "sort" | "group" => {}
+            "window" => window = Some(value.clone()),
+            tag => filters.push(Filter {
+                tag: tag.into(),
+                op: match fold {
+                    true => Op::Contains,
+                    false => Op::Eq,
+                },
+                value: value.clone(),
+            }),
+        }
+    }
+    match filters
+        .iter()
+        .find(|filter| filter.tag != "any" && tag_name(&filter.tag).is_none())
+    {
+        Some(filter) => Err(ack(ACK_ARG, format!("Unknown tag type: {}", filter.tag))),
+        None => Ok((filters, window)),
+    }
+}
+
+/// Parses a single expression, nested ones are joined with AND.
+fn expression(chars: &mut Peekable<Chars>, filters: &mut Vec<Filter>) -> Result<(), Ack> {
+    let bad = || ack(ACK_ARG, "Malformed filter expression");
+    let skip =
+        |chars: &mut Peekable<Chars>| while chars.next_if(|c| c.is_whitespace()).is_some() {};
+    let word = |chars: &mut Peekable<Chars>| {
+        let mut word = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '(' && *c != ')') {
+            word.push(c)
+        }
+        word
+    };
+    skip(chars);
+    if chars.next() != Some('(') {
+        return Err(bad());
+    }
+    skip(chars);
+    if chars.peek() == Some(&'(') {
+        loop {
+            expression(chars, filters)?;
+            skip(chars);
+            if chars.next_if_eq(&')').is_some() {
+                return Ok(());
+            }
+            if word(chars) != "AND" {
+                return Err(bad());
+            }
+        }
+    }
+    let tag = word(chars).to_lowercase();
+    skip(chars);
+    let op = match word(chars).as_str() {
+        "==" => Op::Eq,
+        "!=" => Op::Ne,
+        "contains" => Op::Contains,
+        _ => return Err(bad()),
+    };
+    skip(chars);
+    let quote = chars.next_if(|c| *c == '"' || *c == '\'').ok_or_else(bad)?;
+    let mut value = String::new();
+    loop {
+        match chars.next() {
+            Some(c) if c == quote => break,
+            Some('\\') => value.extend(chars.next()),
+            Some(c) => value.push(c),
+            None => return Err(bad()),
+        }
+    }
+    skip(chars);
+    if chars.next() != Some(')') {
+        return Err(bad());
+    }
+    filters.push(Filter { tag, op, value });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env,
+        f32::consts::TAU,
+        fs,
+        path::Path,
+        sync::{atomic::AtomicU32, Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use hound::{SampleFormat, WavSpec, WavWriter};
+    use serde_json::json;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+        time::sleep,
+    };
+
+    use super::{filters, range, split, Client, Filter, Op, ACK_ARG};
+    use crate::{
+        database::{Gain, Song, DB},
+        player::{bus, Player},
+        server::Config,
+    };
+
+    fn client(db_path: &str) -> Client {
+        let conf: Config = serde_json::from_value(json!({
+            "db_path": db_path,
+            "music": ["/music/"],
+            "addr": "127.0.0.1:0",
+            "output": { "type": "null" },
+        }))
+        .unwrap();
+        let events = bus();
+        Client {
+            ply: Player::new(&conf, events.clone()),
+            db: Arc::new(Mutex::new(DB::open(db_path).unwrap())),
+            music: conf.music.clone(),
+            conf: Arc::new(Mutex::new(conf)),
+            events,
+            version: Arc::new(AtomicU32::new(1)),
+            started: Instant::now(),
+        }
+    }
+
+    fn song(file: &str) -> Song {
+        Song {
+            song_id: 1,
+            artist_id: 1,
+            album_id: 1,
+            title: "It's Tone".into(),
+            artist: "Test Artist".into(),
+            album: "Test Album".into(),
+            ms: 5_000,
+            inferred: false,
+            file: file.into(),
+            size: 0,
+            gain: Gain::default(),
+        }
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn show(filters: &[Filter]) -> Vec<(&str, Op, &str)> {
+        filters
+            .iter()
+            .map(|filter| (filter.tag.as_str(), filter.op, filter.value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn splits_command_lines() {
+        let line = r#"find  Artist "The \"Best\" Band" album "" "back\\slash""#;
+        let split = split(line).ok().unwrap();
+        assert_eq!(
+            split,
+            [
+                "find",
+                "Artist",
+                r#"The "Best" Band"#,
+                "album",
+                "",
+                r"back\slash"
+            ]
+        );
+        assert_eq!(super::split("  ").ok().unwrap(), Vec::<String>::new());
+        assert_eq!(super::split("play\t3").ok().unwrap(), ["play", "3"]);
+        let unclosed = super::split(r#"add "open"#).err().unwrap();
+        assert_eq!(unclosed.code, ACK_ARG);
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(range("3", 5).ok(), Some(3..4));
+        assert_eq!(range("1:3", 5).ok(), Some(1..3));
+        assert_eq!(range("2:", 5).ok(), Some(2..5));
+        assert_eq!(range("1:99", 5).ok(), Some(1..5));
+        for bad in ["5", "3:1", "2:2", "x", "1:x", ":", "-1"] {
+            assert!(range(bad, 5).is_err(), "{bad} was accepted");
+        }
+    }
+
+    #[test]
+    fn parses_filters() {
+        let (found, window) = filters(&args(&["Artist", "A", "window", "0:2"]), false)
+            .ok()
+            .unwrap();
+        assert_eq!(show(&found), [("artist", Op::Eq, "A")]);
+        assert_eq!(window.as_deref(), Some("0:2"));
+        let (found, _) = filters(&args(&["any", "a", "sort", "Title"]), true)
+            .ok()
+            .unwrap();
+        assert_eq!(show(&found), [("any", Op::Contains, "a")]);
+
+        let expr = r#"((Artist == "A B") AND (album != 'It\'s') AND (title contains "x"))"#;
+        let (found, _) = filters(&args(&[expr]), false).ok().unwrap();
+        assert_eq!(
+            show(&found),
+            [
+                ("artist", Op::Eq, "A B"),
+                ("album", Op::Ne, "It's"),
+                ("title", Op::Contains, "x")
+            ]
+        );
+
+        for bad in [
+            &["artist"][..],
+            &["genre", "Rock"],
+            &["(artist == 'A'"],
+            &["(artist ~= 'A')"],
+            &["(artist == A)"],
+            &["((artist == 'A') OR (album == 'B'))"],
+        ] {
+            let failed = filters(&args(bad), false);
+            assert_eq!(failed.err().map(|ack| ack.code), Some(ACK_ARG), "{bad:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn matches_songs() {
+        let client = client(":memory:");
+        let song = song("/music/Test Artist/Test Album/01 - Tone.wav");
+        let matches = |terms: &[&str], fold: bool| {
+            let (found, _) = filters(&args(terms), fold).ok().unwrap();
+            client.matches(&song, &found, fold)
+        };
+        assert!(matches(&["artist", "Test Artist"], false));
+        assert!(!matches(&["artist", "test artist"], false));
+        assert!(matches(&["artist", "test"], true));
+        assert!(matches(
+            &["file", "Test Artist/Test Album/01 - Tone.wav"],
+            false
+        ));
+        assert!(matches(&["any", "tone.wav"], true));
+        assert!(matches(&["(title == \"It's Tone\")"], false));
+        assert!(matches(
+            &["(album != 'Other')", "artist", "Test Artist"],
+            false
+        ));
+        assert!(!matches(
+            &["((album contains 'Test') AND (title != \"It's Tone\"))"],
+            false
+        ));
+    }
+
+    /// Sends `lines` and reads the reply up to its final OK or ACK.
+    async fn exchange(stream: &mut BufReader<TcpStream>, lines: &str) -> Vec<String> {
+        stream.get_mut().write_all(lines.as_bytes()).await.unwrap();
+        let mut reply = vec![];
+        loop {
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            let line = line.trim_end().to_string();
+            let done = line == "OK" || line.starts_with("ACK");
+            reply.push(line);
+            if done {
+                return reply;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn frames_command_lists() {
+        let client = client(":memory:");
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            client.run(stream).await
+        });
+        let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        let mut greeting = String::new();
+        stream.read_line(&mut greeting).await.unwrap();
+        assert!(greeting.starts_with("OK MPD "));
+
+        let reply = exchange(
+            &mut stream,
+            "command_list_ok_begin\nping\nping\ncommand_list_end\n",
+        )
+        .await;
+        assert_eq!(reply, ["list_OK", "list_OK", "OK"]);
+        let reply = exchange(
+            &mut stream,
+            "command_list_begin\nping\nping\ncommand_list_end\n",
+        )
+        .await;
+        assert_eq!(reply, ["OK"]);
+        // the first failure ends the list and names the command's place in it
+        let reply = exchange(
+            &mut stream,
+            "command_list_ok_begin\nping\nfrobnicate\nping\ncommand_list_end\n",
+        )
+        .await;
+        assert_eq!(
+            reply,
+            [
+                "list_OK",
+                "ACK [5@1] {frobnicate} unknown command \"frobnicate\""
+            ]
+        );
+        let reply = exchange(&mut stream, "\"unclosed\n").await;
+        assert_eq!(reply, ["ACK [2@0] {} Missing closing '\"'"]);
+        assert_eq!(exchange(&mut stream, "ping\n").await, ["OK"]);
+    }
+
+    fn tone(path: &Path) {
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 48_000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(path, spec).unwrap();
+        for i in 0..48_000 * 5 {
+            let sample = ((i as f32 * 440. / 48_000. * TAU).sin() * 16_000.) as i16;
+            writer.write_sample(sample).unwrap();
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap()
+    }
+
+    #[tokio::test]
+    async fn stop_reports_the_stop_state() {
+        let dir = env::temp_dir().join(format!("yampd-mpd-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("tone.wav");
+        tone(&file);
+        let client = client(dir.join("yampd.db").to_str().unwrap());
+        client.ply.push(song(file.to_str().unwrap())).unwrap();
+        client.command("play", &[]).await.ok().unwrap();
+        sleep(Duration::from_millis(300)).await;
+        let status = client.command("status", &[]).await.ok().unwrap();
+        assert!(status.contains("state: play\n"), "{status}");
+
+        client.command("stop", &[]).await.ok().unwrap();
+        let status = client.command("status", &[]).await.ok().unwrap();
+        assert!(status.contains("state: stop\n"), "{status}");
+        assert!(status.contains("song: 0\n"), "{status}");
+        assert!(!status.contains("elapsed"), "{status}");
+
+        // playing again starts the song over
+        client.command("play", &[]).await.ok().unwrap();
+        let status = client.command("status", &[]).await.ok().unwrap();
+        assert!(status.contains("state: play\n"), "{status}");
+        let elapsed: f64 = status
+            .lines()
+            .find_map(|line| line.strip_prefix("elapsed: "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(elapsed < 0.3, "{status}");
+        drop(client);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,301 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio_stream::{wrappers::BroadcastStream, StreamExt};
+use zbus::{
+    fdo, interface,
+    zvariant::{ObjectPath, OwnedValue, Value},
+    SignalContext,
+};
+
+use crate::player::{self, Event, Events, Player, Repeat};
+
+use super::Config;
+
+const PATH: &str = "/org/mpris/MediaPlayer2";
+
+impl From<player::Error> for fdo::Error {
+    fn from(err: player::Error) -> Self {
+        fdo::Error::Failed(err.to_string())
+    }
+}
+
+struct Root;
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {}
+    fn quit(&self) {}
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        false
+    }
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+    #[zbus(property)]
+    fn identity(&self) -> String {
+        "yampd".into()
+    }
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        vec![]
+    }
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+struct MprisPlayer {
+    ply: Player,
+    conf: Arc<Mutex<Config>>,
+}
+
+impl MprisPlayer {
+    fn track_id(id: u32) -> ObjectPath<'static> {
+        ObjectPath::try_from(format!("/org/yampd/track/{id}")).unwrap()
+    }
+    /// Applies `change` to the config and stores it.
+    fn save(&self, change: impl FnOnce(&mut Config)) -> fdo::Result<()> {
+        let mut conf = self.conf.lock().unwrap();
+        change(&mut conf);
+        conf.write()
+            .map_err(|err| fdo::Error::Failed(format!("could not save the config: {err}")))
+    }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl MprisPlayer {
+    fn next(&self) -> fdo::Result<()> {
+        Ok(self.ply.next()?)
+    }
+    fn previous(&self) -> fdo::Result<()> {
+        Ok(self.ply.prev()?)
+    }
+    fn pause(&self) -> fdo::Result<()> {
+        Ok(self.ply.set_pause(true)?)
+    }
+    async fn play_pause(&self) -> fdo::Result<()> {
+        let status = self.ply.status().await?;
+        match status.song {
+            Some(_) if status.duration.is_zero() => self.ply.play()?,
+            Some(_) => self.ply.set_pause(!status.paused)?,
+            None => {}
+        }
+        Ok(())
+    }
+    fn stop(&self) -> fdo::Result<()> {
+        Ok(self.ply.stop()?)
+    }
+    async fn play(&self) -> fdo::Result<()> {
+        match self.ply.status().await?.duration.is_zero() {
+            true => self.ply.play()?,
+            false => self.ply.set_pause(false)?,
+        }
+        Ok(())
+    }
+    /// `offset` in microseconds, relative to the current position.
+    async fn seek(&self, offset: i64) -> fdo::Result<()> {
+        let status = self.ply.status().await?;
+        let pos = status.position.as_micros() as i64 + offset;
+        match pos > status.duration.as_micros() as i64 {
+            true => self.ply.next()?,
+            false => self
+                .ply
+                .set_position(Duration::from_micros(pos.max(0) as u64))?,
+        }
+        Ok(())
+    }
+    async fn set_position(&self, track_id: ObjectPath<'_>, position: i64) -> fdo::Result<()> {
+        let status = self.ply.status().await?;
+        let Some(now) = status.song else {
+            return Ok(());
+        };
+        if track_id == MprisPlayer::track_id(now.song_id)
+            && position >= 0
+            && position as u128 <= status.duration.as_micros()
+        {
+            self.ply
+                .set_position(Duration::from_micros(position as u64))?
+        }
+        Ok(())
+    }
+    fn open_uri(&self, _uri: String) {}
+    #[zbus(signal)]
+    async fn seeked(ctxt: &SignalContext<'_>, position: i64) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    async fn playback_status(&self) -> fdo::Result<String> {
+        let status = self.ply.status().await?;
+        Ok(match (status.song, status.paused) {
+            (None, _) => "Stopped",
+            (Some(_), true) => "Paused",
+            (Some(_), false) if status.duration.is_zero() => "Stopped",
+            (Some(_), false) => "Playing",
+        }
+        .into())
+    }
+    #[zbus(property)]
+    async fn loop_status(&self) -> fdo::Result<String> {
+        Ok(match self.ply.mode().await?.repeat {
+            Repeat::Off => "None",
+            Repeat::One => "Track",
+            Repeat::All => "Playlist",
+        }
+        .into())
+    }
+    #[zbus(property)]
+    async fn set_loop_status(&mut self, status: String) -> fdo::Result<()> {
+        let mut mode = self.ply.mode().await?;
+        mode.repeat = match status.as_str() {
+            "Track" => Repeat::One,
+            "Playlist" => Repeat::All,
+            _ => Repeat::Off,
+        };
+        self.ply.set_mode(mode)?;
+        self.save(|conf| conf.mode = mode)
+    }
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1.
+    }
+    #[zbus(property)]
+    fn set_rate(&mut self, _rate: f64) {}
+    #[zbus(property)]
+    async fn shuffle(&self) -> fdo::Result<bool> {
+        Ok(self.ply.mode().await?.shuffle)
+    }
+    #[zbus(property)]
+    async fn set_shuffle(&mut self, shuffle: bool) -> fdo::Result<()> {
+        let mut mode = self.ply.mode().await?;
+        mode.shuffle = shuffle;
+        self.ply.set_mode(mode)?;
+        self.save(|conf| conf.mode = mode)
+    }
+    #[zbus(property)]
+    async fn metadata(&self) -> fdo::Result<HashMap<String, OwnedValue>> {
+        let mut meta = HashMap::new();
+        let Some(song) = self.ply.status().await?.song else {
+            return Ok(meta);
+        };
+        let addr = self.conf.lock().unwrap().addr.clone();
+        let mut insert = |key: &str, value: Value| {
+            meta.insert(key.into(), OwnedValue::try_from(value).unwrap());
+        };
+        insert("mpris:trackid", MprisPlayer::track_id(song.song_id).into());
+        insert("mpris:length", (song.ms as i64 * 1_000).into());
+        insert(
+            "mpris:artUrl",
+            format!("http://{addr}/lib/cover/{}", song.album_id).into(),
+        );
+        insert("xesam:title", song.title.into());
+        insert("xesam:artist", vec![song.artist].into());
+        insert("xesam:album", song.album.into());
+        Ok(meta)
+    }
+    #[zbus(property)]
+    async fn volume(&self) -> fdo::Result<f64> {
+        Ok(self.ply.volume().await?.level as f64)
+    }
+    #[zbus(property)]
+    async fn set_volume(&mut self, level: f64) -> fdo::Result<()> {
+        if !level.is_finite() {
+            return Err(fdo::Error::InvalidArgs(format!("Bad volume: {level}")));
+        }
+        let mut volume = self.ply.volume().await?;
+        volume.level = level as f32;
+        let volume = volume.clamped();
+        self.ply.set_volume(volume)?;
+        self.save(|conf| conf.volume = volume)
+    }
+    #[zbus(property(emits_changed_signal = "false"))]
+    async fn position(&self) -> fdo::Result<i64> {
+        Ok(self.ply.status().await?.position.as_micros() as i64)
+    }
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.
+    }
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        1.
+    }
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        true
+    }
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        true
+    }
+    #[zbus(property)]
+    async fn can_play(&self) -> fdo::Result<bool> {
+        Ok(self.ply.status().await?.song.is_some())
+    }
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        true
+    }
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+/// Registers yampd on the session bus and forwards player events as PropertiesChanged signals.
+pub async fn serve(ply: Player, conf: Arc<Mutex<Config>>, events: Events) -> zbus::Result<()> {
+    let conn = zbus::connection::Builder::session()?
+        .name("org.mpris.MediaPlayer2.yampd")?
+        .serve_at(PATH, Root)?
+        .serve_at(PATH, MprisPlayer { ply, conf })?
+        .build()
+        .await?;
+    let iface = conn
+        .object_server()
+        .interface::<_, MprisPlayer>(PATH)
+        .await?;
+    let ctxt = iface.signal_context();
+    let mut stream = BroadcastStream::new(events.subscribe());
+    while let Some(event) = stream.next().await {
+        let Ok(event) = event else {
+            continue;
+        };
+        let player = iface.get().await;
+        match event {
+            Event::TrackStarted { .. } => {
+                player.metadata_changed(ctxt).await?;
+                player.playback_status_changed(ctxt).await?;
+                player.can_play_changed(ctxt).await?;
+            }
+            Event::TrackEnded { .. } | Event::Paused { .. } | Event::Stopped => {
+                player.playback_status_changed(ctxt).await?
+            }
+            Event::Seeked { pos } => {
+                MprisPlayer::seeked(ctxt, pos as i64 * 1_000).await?;
+            }
+            Event::QueueChanged { .. } => {
+                player.loop_status_changed(ctxt).await?;
+                player.shuffle_changed(ctxt).await?;
+                player.can_play_changed(ctxt).await?;
+                player.metadata_changed(ctxt).await?;
+                player.playback_status_changed(ctxt).await?;
+            }
+            Event::VolumeChanged { .. } => player.volume_changed(ctxt).await?,
+            Event::Scan { .. } | Event::LibraryChanged { .. } | Event::PlaybackError { .. } => {}
+        }
+    }
+    Ok(())
+}
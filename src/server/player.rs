@@ -4,24 +4,39 @@ use std::{
 };
 
 use axum::{
+    body::Body,
     extract::Path,
     http::StatusCode,
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     routing::{get, post},
     Extension, Json, Router,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
 use crate::{
-    database::{Song, DB},
-    player::Player,
+    database::{Format, Preset, Song, DB},
+    player::{Crossfade, Eq, Error, Mode, Player, ReplayGain, SongError, Volume},
 };
 
+use super::Config;
+
 #[derive(Debug, Serialize, ToSchema)]
 pub struct Queue {
     index: usize,
     songs: Vec<Song>,
+    mode: Mode,
+    /// indices into `songs` in the order they are played
+    order: Vec<usize>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct Move {
+    pub from: usize,
+    /// number of entries moved, 1 if not given
+    pub count: Option<usize>,
+    /// position of the first moved entry afterwards
+    pub to: usize,
 }
 
 #[derive(Debug, Serialize, ToSchema)]
@@ -30,6 +45,23 @@ pub struct Now {
     pos: u128,
     dur: u128,
     pause: bool,
+    volume: Volume,
+    /// times the output ran out of decoded samples since startup
+    underruns: u64,
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        (StatusCode::SERVICE_UNAVAILABLE, self.to_string()).into_response()
+    }
+}
+
+/// Stores the changed config, answering 500 when it can not be written.
+fn save(conf: &Config) -> Result<(), (StatusCode, String)> {
+    conf.write().map_err(|err| {
+        let msg = format!("could not save the config: {err}");
+        (StatusCode::INTERNAL_SERVER_ERROR, msg)
+    })
 }
 
 pub fn player() -> Router {
@@ -43,10 +75,29 @@ pub fn player() -> Router {
         .route("/pos/seek/forw/:ms", post(pos_seek_forw))
         .route("/pos/seek/back/:ms", post(pos_seek_back))
         .route("/pos/set/:ms", post(pos_set))
+        .route("/pos/percent/:percent", post(pos_percent))
         .route("/queue", get(queue))
         .route("/queue/song/:id", post(queue_song))
         .route("/queue/album/:id", post(queue_album))
+        .route("/queue/song/:id/at/:index", post(insert_song))
+        .route("/queue/album/:id/at/:index", post(insert_album))
+        .route("/queue/song/:id/next", post(next_song))
+        .route("/queue/album/:id/next", post(next_album))
+        .route("/queue/move", post(queue_move))
+        .route("/queue/clear", post(queue_clear))
+        .route("/queue/crop", post(queue_crop))
+        .route("/queue/export/:format", get(queue_export))
         .route("/now", get(now))
+        .route("/errors", get(errors))
+        .route("/mode", get(mode).post(set_mode))
+        .route("/crossfade", get(crossfade).post(set_crossfade))
+        .route("/replaygain", get(replaygain).post(set_replaygain))
+        .route("/volume", get(volume).post(set_volume))
+        .route("/volume/mute", post(mute))
+        .route("/eq", get(eq).post(set_eq))
+        .route("/eq/preset", get(presets))
+        .route("/eq/preset/:name", post(save_preset).delete(delete_preset))
+        .route("/eq/preset/:name/select", post(select_preset))
 }
 #[utoipa::path(
     post,
@@ -55,8 +106,8 @@ pub fn player() -> Router {
         (status = 200),
     )
 )]
-pub async fn play(Extension(ply): Extension<Arc<Mutex<Player>>>) {
-    ply.lock().unwrap().play()
+pub async fn play(Extension(ply): Extension<Player>) -> Result<(), Error> {
+    ply.play()
 }
 #[utoipa::path(
     post,
@@ -65,8 +116,8 @@ pub async fn play(Extension(ply): Extension<Arc<Mutex<Player>>>) {
         (status = 200),
     )
 )]
-pub async fn pause(Extension(ply): Extension<Arc<Mutex<Player>>>) {
-    ply.lock().unwrap().set_pause(true)
+pub async fn pause(Extension(ply): Extension<Player>) -> Result<(), Error> {
+    ply.set_pause(true)
 }
 #[utoipa::path(
     post,
@@ -75,8 +126,8 @@ pub async fn pause(Extension(ply): Extension<Arc<Mutex<Player>>>) {
         (status = 200),
     )
 )]
-pub async fn unpause(Extension(ply): Extension<Arc<Mutex<Player>>>) {
-    ply.lock().unwrap().set_pause(false)
+pub async fn unpause(Extension(ply): Extension<Player>) -> Result<(), Error> {
+    ply.set_pause(false)
 }
 #[utoipa::path(
     post,
@@ -85,8 +136,8 @@ pub async fn unpause(Extension(ply): Extension<Arc<Mutex<Player>>>) {
         (status = 200),
     )
 )]
-pub async fn next(Extension(ply): Extension<Arc<Mutex<Player>>>) {
-    ply.lock().unwrap().next()
+pub async fn next(Extension(ply): Extension<Player>) -> Result<(), Error> {
+    ply.next()
 }
 #[utoipa::path(
     post,
@@ -95,8 +146,8 @@ pub async fn next(Extension(ply): Extension<Arc<Mutex<Player>>>) {
         (status = 200),
     )
 )]
-pub async fn prev(Extension(ply): Extension<Arc<Mutex<Player>>>) {
-    ply.lock().unwrap().prev()
+pub async fn prev(Extension(ply): Extension<Player>) -> Result<(), Error> {
+    ply.prev()
 }
 #[utoipa::path(
     post,
@@ -105,8 +156,11 @@ pub async fn prev(Extension(ply): Extension<Arc<Mutex<Player>>>) {
         (status = 200),
     )
 )]
-pub async fn index(Extension(ply): Extension<Arc<Mutex<Player>>>, Path(index): Path<usize>) {
-    ply.lock().unwrap().index(index)
+pub async fn index(
+    Extension(ply): Extension<Player>,
+    Path(index): Path<usize>,
+) -> Result<(), Error> {
+    ply.index(index)
 }
 #[utoipa::path(
     delete,
@@ -115,8 +169,11 @@ pub async fn index(Extension(ply): Extension<Arc<Mutex<Player>>>, Path(index): P
         (status = 200),
     )
 )]
-pub async fn delete(Extension(ply): Extension<Arc<Mutex<Player>>>, Path(index): Path<usize>) {
-    ply.lock().unwrap().delete(index)
+pub async fn delete(
+    Extension(ply): Extension<Player>,
+    Path(index): Path<usize>,
+) -> Result<(), Error> {
+    ply.delete(index)
 }
 #[utoipa::path(
     post,
@@ -125,10 +182,12 @@ pub async fn delete(Extension(ply): Extension<Arc<Mutex<Player>>>, Path(index):
         (status = 200),
     )
 )]
-pub async fn pos_seek_forw(Extension(ply): Extension<Arc<Mutex<Player>>>, Path(ms): Path<u32>) {
-    let lock = ply.lock().unwrap();
-    let now = lock.position();
-    lock.set_position(now + Duration::from_millis(ms.into()))
+pub async fn pos_seek_forw(
+    Extension(ply): Extension<Player>,
+    Path(ms): Path<u32>,
+) -> Result<(), Error> {
+    let now = ply.status().await?.position;
+    ply.set_position(now + Duration::from_millis(ms.into()))
 }
 #[utoipa::path(
     post,
@@ -137,11 +196,13 @@ pub async fn pos_seek_forw(Extension(ply): Extension<Arc<Mutex<Player>>>, Path(m
         (status = 200),
     )
 )]
-pub async fn pos_seek_back(Extension(ply): Extension<Arc<Mutex<Player>>>, Path(ms): Path<u32>) {
-    let lock = ply.lock().unwrap();
-    let now = lock.position();
+pub async fn pos_seek_back(
+    Extension(ply): Extension<Player>,
+    Path(ms): Path<u32>,
+) -> Result<(), Error> {
+    let now = ply.status().await?.position;
     let dur = Duration::from_millis(ms.into());
-    lock.set_position(match now < dur {
+    ply.set_position(match now < dur {
         true => Duration::from_secs(0),
         false => now - dur,
     })
@@ -153,10 +214,26 @@ pub async fn pos_seek_back(Extension(ply): Extension<Arc<Mutex<Player>>>, Path(m
         (status = 200),
     )
 )]
-pub async fn pos_set(Extension(ply): Extension<Arc<Mutex<Player>>>, Path(ms): Path<u32>) {
-    ply.lock()
-        .unwrap()
-        .set_position(Duration::from_millis(ms.into()))
+pub async fn pos_set(Extension(ply): Extension<Player>, Path(ms): Path<u32>) -> Result<(), Error> {
+    ply.set_position(Duration::from_millis(ms.into()))
+}
+#[utoipa::path(
+    post,
+    path = "/ply/pos/percent/{percent}",
+    responses(
+        (status = 200),
+    )
+)]
+pub async fn pos_percent(
+    Extension(ply): Extension<Player>,
+    Path(percent): Path<f64>,
+) -> Result<(), Error> {
+    let dur = ply.status().await?.duration;
+    let percent = match percent.is_nan() {
+        true => 0.,
+        false => percent.clamp(0., 100.),
+    };
+    ply.set_position(dur.mul_f64(percent / 100.))
 }
 
 #[utoipa::path(
@@ -167,18 +244,20 @@ pub async fn pos_set(Extension(ply): Extension<Arc<Mutex<Player>>>, Path(ms): Pa
     )
 )]
 pub async fn queue(
-    Extension(ply): Extension<Arc<Mutex<Player>>>,
+    Extension(ply): Extension<Player>,
     Extension(db): Extension<Arc<Mutex<DB>>>,
-) -> impl IntoResponse {
-    let q = ply.lock().unwrap().queue();
-    Json(Queue {
+) -> Result<Json<Queue>, Error> {
+    let q = ply.queue().await?;
+    Ok(Json(Queue {
         index: q.index,
         songs: q
             .songs
             .iter()
-            .filter_map(|(id, _)| Song::by_id(&db.lock().unwrap(), id.clone()))
+            .filter_map(|song| Song::by_id(&db.lock().unwrap(), song.song_id))
             .collect(),
-    })
+        mode: q.mode,
+        order: q.order,
+    }))
 }
 #[utoipa::path(
     post,
@@ -188,12 +267,13 @@ pub async fn queue(
     )
 )]
 pub async fn queue_song(
-    Extension(ply): Extension<Arc<Mutex<Player>>>,
+    Extension(ply): Extension<Player>,
     Extension(db): Extension<Arc<Mutex<DB>>>,
     Path(id): Path<u32>,
-) {
-    if let Some(song) = Song::by_id(&db.lock().unwrap(), id) {
-        ply.lock().unwrap().push(song)
+) -> Result<(), Error> {
+    match Song::by_id(&db.lock().unwrap(), id) {
+        Some(song) => ply.push(song),
+        None => Ok(()),
     }
 }
 #[utoipa::path(
@@ -204,14 +284,139 @@ pub async fn queue_song(
     )
 )]
 pub async fn queue_album(
-    Extension(ply): Extension<Arc<Mutex<Player>>>,
+    Extension(ply): Extension<Player>,
     Extension(db): Extension<Arc<Mutex<DB>>>,
     Path(id): Path<u32>,
-) {
+) -> Result<(), Error> {
     for song in Song::by_album_id(&db.lock().unwrap(), id) {
-        ply.lock().unwrap().push(song)
+        ply.push(song)?
+    }
+    Ok(())
+}
+#[utoipa::path(
+    post,
+    path = "/ply/queue/song/{id}/at/{index}",
+    responses(
+        (status = 200),
+    )
+)]
+pub async fn insert_song(
+    Extension(ply): Extension<Player>,
+    Extension(db): Extension<Arc<Mutex<DB>>>,
+    Path((id, index)): Path<(u32, usize)>,
+) -> Result<(), Error> {
+    match Song::by_id(&db.lock().unwrap(), id) {
+        Some(song) => ply.insert(index, vec![song]),
+        None => Ok(()),
+    }
+}
+#[utoipa::path(
+    post,
+    path = "/ply/queue/album/{id}/at/{index}",
+    responses(
+        (status = 200),
+    )
+)]
+pub async fn insert_album(
+    Extension(ply): Extension<Player>,
+    Extension(db): Extension<Arc<Mutex<DB>>>,
+    Path((id, index)): Path<(u32, usize)>,
+) -> Result<(), Error> {
+    let songs = Song::by_album_id(&db.lock().unwrap(), id);
+    ply.insert(index, songs)
+}
+#[utoipa::path(
+    post,
+    path = "/ply/queue/song/{id}/next",
+    responses(
+        (status = 200, description = "Queues the song right after the current one"),
+    )
+)]
+pub async fn next_song(
+    Extension(ply): Extension<Player>,
+    Extension(db): Extension<Arc<Mutex<DB>>>,
+    Path(id): Path<u32>,
+) -> Result<(), Error> {
+    match Song::by_id(&db.lock().unwrap(), id) {
+        Some(song) => ply.play_next(vec![song]),
+        None => Ok(()),
     }
 }
+#[utoipa::path(
+    post,
+    path = "/ply/queue/album/{id}/next",
+    responses(
+        (status = 200, description = "Queues the album right after the current song"),
+    )
+)]
+pub async fn next_album(
+    Extension(ply): Extension<Player>,
+    Extension(db): Extension<Arc<Mutex<DB>>>,
+    Path(id): Path<u32>,
+) -> Result<(), Error> {
+    let songs = Song::by_album_id(&db.lock().unwrap(), id);
+    ply.play_next(songs)
+}
+#[utoipa::path(
+    post,
+    path = "/ply/queue/move",
+    request_body = Move,
+    responses(
+        (status = 200),
+    )
+)]
+pub async fn queue_move(
+    Extension(ply): Extension<Player>,
+    Json(payload): Json<Move>,
+) -> Result<(), Error> {
+    ply.move_range(payload.from, payload.count.unwrap_or(1), payload.to)
+}
+#[utoipa::path(
+    post,
+    path = "/ply/queue/clear",
+    responses(
+        (status = 200),
+    )
+)]
+pub async fn queue_clear(Extension(ply): Extension<Player>) -> Result<(), Error> {
+    ply.clear()
+}
+#[utoipa::path(
+    post,
+    path = "/ply/queue/crop",
+    responses(
+        (status = 200, description = "Removes everything but the current song"),
+    )
+)]
+pub async fn queue_crop(Extension(ply): Extension<Player>) -> Result<(), Error> {
+    ply.crop()
+}
+#[utoipa::path(
+    get,
+    path = "/ply/queue/export/{format}",
+    responses(
+        (status = 200, description = "Queue as m3u, m3u8, pls or xspf file"),
+        (status = 400, description = "Unknown format"),
+    )
+)]
+pub async fn queue_export(
+    Extension(ply): Extension<Player>,
+    Path(format): Path<String>,
+) -> impl IntoResponse {
+    let Some(format) = Format::from_ext(&format) else {
+        return (StatusCode::BAD_REQUEST).into_response();
+    };
+    let queue = match ply.queue().await {
+        Ok(queue) => queue,
+        Err(err) => return err.into_response(),
+    };
+    Response::builder()
+        .header("Content-Type", format.mime())
+        .status(StatusCode::OK)
+        .body(Body::from(format.export("queue", &queue.songs)))
+        .unwrap()
+        .into_response()
+}
 #[utoipa::path(
     get,
     path = "/ply/now",
@@ -220,20 +425,282 @@ pub async fn queue_album(
         (status = 404, description = "Not playing")
     )
 )]
-pub async fn now(Extension(ply): Extension<Arc<Mutex<Player>>>) -> impl IntoResponse {
-    let lock = ply.lock().unwrap();
-    if let Some((id, _)) = lock.now() {
-        (
-            StatusCode::OK,
-            Json(Now {
-                id: id,
-                pos: lock.position().as_millis(),
-                dur: lock.duration().as_millis(),
-                pause: lock.is_paused(),
-            }),
-        )
-            .into_response()
-    } else {
-        (StatusCode::NOT_FOUND).into_response()
+pub async fn now(Extension(ply): Extension<Player>) -> Result<Response, Error> {
+    let status = ply.status().await?;
+    Ok(match status.song {
+        Some(song) => Json(Now {
+            id: song.song_id,
+            pos: status.position.as_millis(),
+            dur: status.duration.as_millis(),
+            pause: status.paused,
+            volume: status.volume,
+            underruns: status.underruns,
+        })
+        .into_response(),
+        None => (StatusCode::NOT_FOUND).into_response(),
+    })
+}
+#[utoipa::path(
+    get,
+    path = "/ply/errors",
+    responses(
+        (status = 200, body = Vec<SongError>),
+    )
+)]
+pub async fn errors(Extension(ply): Extension<Player>) -> Result<Json<Vec<SongError>>, Error> {
+    Ok(Json(ply.errors().await?))
+}
+#[utoipa::path(
+    get,
+    path = "/ply/mode",
+    responses(
+        (status = 200, body = Mode),
+    )
+)]
+pub async fn mode(Extension(ply): Extension<Player>) -> Result<Json<Mode>, Error> {
+    Ok(Json(ply.mode().await?))
+}
+#[utoipa::path(
+    post,
+    path = "/ply/mode",
+    request_body = Mode,
+    responses(
+        (status = 200, description = "Sets repeat, shuffle and consume, turning shuffle on reshuffles the queue"),
+        (status = 500, description = "The config file could not be written")
+    )
+)]
+pub async fn set_mode(
+    Extension(ply): Extension<Player>,
+    Extension(conf): Extension<Arc<Mutex<Config>>>,
+    Json(payload): Json<Mode>,
+) -> Result<(), Response> {
+    ply.set_mode(payload).map_err(IntoResponse::into_response)?;
+    let mut conf = conf.lock().unwrap();
+    conf.mode = payload;
+    save(&conf).map_err(IntoResponse::into_response)
+}
+#[utoipa::path(
+    get,
+    path = "/ply/crossfade",
+    responses(
+        (status = 200, body = Crossfade),
+    )
+)]
+pub async fn crossfade(Extension(ply): Extension<Player>) -> Result<Json<Crossfade>, Error> {
+    Ok(Json(ply.crossfade().await?))
+}
+#[utoipa::path(
+    post,
+    path = "/ply/crossfade",
+    request_body = Crossfade,
+    responses(
+        (status = 200),
+        (status = 400, description = "secs is not a finite number"),
+        (status = 500, description = "The config file could not be written")
+    )
+)]
+pub async fn set_crossfade(
+    Extension(ply): Extension<Player>,
+    Extension(conf): Extension<Arc<Mutex<Config>>>,
+    Json(payload): Json<Crossfade>,
+) -> Result<(), Response> {
+    let Some(crossfade) = payload.checked() else {
+        return Err((StatusCode::BAD_REQUEST, "secs must be a finite number").into_response());
+    };
+    ply.set_crossfade(crossfade)
+        .map_err(IntoResponse::into_response)?;
+    let mut conf = conf.lock().unwrap();
+    conf.crossfade = crossfade;
+    save(&conf).map_err(IntoResponse::into_response)
+}
+#[utoipa::path(
+    get,
+    path = "/ply/replaygain",
+    responses(
+        (status = 200, body = ReplayGain),
+    )
+)]
+pub async fn replaygain(Extension(ply): Extension<Player>) -> Result<Json<ReplayGain>, Error> {
+    Ok(Json(ply.replaygain().await?))
+}
+#[utoipa::path(
+    post,
+    path = "/ply/replaygain",
+    request_body = ReplayGain,
+    responses(
+        (status = 200),
+        (status = 500, description = "The config file could not be written")
+    )
+)]
+pub async fn set_replaygain(
+    Extension(ply): Extension<Player>,
+    Extension(conf): Extension<Arc<Mutex<Config>>>,
+    Json(payload): Json<ReplayGain>,
+) -> Result<(), Response> {
+    ply.set_replaygain(payload)
+        .map_err(IntoResponse::into_response)?;
+    let mut conf = conf.lock().unwrap();
+    conf.replaygain = payload;
+    save(&conf).map_err(IntoResponse::into_response)
+}
+#[utoipa::path(
+    get,
+    path = "/ply/volume",
+    responses(
+        (status = 200, body = Volume),
+    )
+)]
+pub async fn volume(Extension(ply): Extension<Player>) -> Result<Json<Volume>, Error> {
+    Ok(Json(ply.volume().await?))
+}
+#[utoipa::path(
+    post,
+    path = "/ply/volume",
+    request_body = Volume,
+    responses(
+        (status = 200, body = Volume),
+        (status = 500, description = "The config file could not be written")
+    )
+)]
+pub async fn set_volume(
+    Extension(ply): Extension<Player>,
+    Extension(conf): Extension<Arc<Mutex<Config>>>,
+    Json(payload): Json<Volume>,
+) -> Result<Json<Volume>, Response> {
+    let volume = payload.clamped();
+    ply.set_volume(volume)
+        .map_err(IntoResponse::into_response)?;
+    let mut conf = conf.lock().unwrap();
+    conf.volume = volume;
+    save(&conf).map_err(IntoResponse::into_response)?;
+    Ok(Json(volume))
+}
+#[utoipa::path(
+    post,
+    path = "/ply/volume/mute",
+    responses(
+        (status = 200, description = "Toggles mute", body = Volume),
+        (status = 500, description = "The config file could not be written")
+    )
+)]
+pub async fn mute(
+    Extension(ply): Extension<Player>,
+    Extension(conf): Extension<Arc<Mutex<Config>>>,
+) -> Result<Json<Volume>, Response> {
+    let mut volume = ply.volume().await.map_err(IntoResponse::into_response)?;
+    volume.mute = !volume.mute;
+    ply.set_volume(volume)
+        .map_err(IntoResponse::into_response)?;
+    let mut conf = conf.lock().unwrap();
+    conf.volume = volume;
+    save(&conf).map_err(IntoResponse::into_response)?;
+    Ok(Json(volume))
+}
+#[utoipa::path(
+    get,
+    path = "/ply/eq",
+    responses(
+        (status = 200, body = Eq),
+    )
+)]
+pub async fn eq(Extension(ply): Extension<Player>) -> Result<Json<Eq>, Error> {
+    Ok(Json(ply.eq().await?))
+}
+#[utoipa::path(
+    post,
+    path = "/ply/eq",
+    request_body = Eq,
+    responses(
+        (status = 200),
+    )
+)]
+pub async fn set_eq(
+    Extension(ply): Extension<Player>,
+    Json(payload): Json<Eq>,
+) -> Result<(), Error> {
+    ply.set_eq(payload)
+}
+#[utoipa::path(
+    get,
+    path = "/ply/eq/preset",
+    responses(
+        (status = 200, body = [Preset]),
+    )
+)]
+pub async fn presets(Extension(db): Extension<Arc<Mutex<DB>>>) -> impl IntoResponse {
+    Json(Preset::all(&db.lock().unwrap()))
+}
+#[utoipa::path(
+    post,
+    path = "/ply/eq/preset/{name}",
+    request_body = Eq,
+    responses(
+        (status = 200, description = "Creates or overwrites the preset", body = Preset),
+        (status = 500, description = "Preset could not be saved"),
+    ),
+    params(
+        ("name" = String, Path, description = "Preset name")
+    )
+)]
+pub async fn save_preset(
+    Path(name): Path<String>,
+    Extension(db): Extension<Arc<Mutex<DB>>>,
+    Json(payload): Json<Eq>,
+) -> impl IntoResponse {
+    match Preset::save(&db.lock().unwrap(), &name, &payload) {
+        Ok(preset) => Json(preset).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+#[utoipa::path(
+    delete,
+    path = "/ply/eq/preset/{name}",
+    responses(
+        (status = 200),
+        (status = 404, description = "Preset not found"),
+        (status = 500, description = "Preset could not be deleted"),
+    ),
+    params(
+        ("name" = String, Path, description = "Preset name")
+    )
+)]
+pub async fn delete_preset(
+    Path(name): Path<String>,
+    Extension(db): Extension<Arc<Mutex<DB>>>,
+) -> impl IntoResponse {
+    match Preset::delete(&db.lock().unwrap(), &name) {
+        Ok(true) => StatusCode::OK,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+#[utoipa::path(
+    post,
+    path = "/ply/eq/preset/{name}/select",
+    responses(
+        (status = 200, description = "Applies the preset and remembers it for the current output", body = Eq),
+        (status = 404, description = "Preset not found"),
+        (status = 500, description = "Preset could not be remembered for the output"),
+    ),
+    params(
+        ("name" = String, Path, description = "Preset name")
+    )
+)]
+pub async fn select_preset(
+    Path(name): Path<String>,
+    Extension(ply): Extension<Player>,
+    Extension(db): Extension<Arc<Mutex<DB>>>,
+    Extension(conf): Extension<Arc<Mutex<Config>>>,
+) -> Result<Json<Eq>, Response> {
+    let db = db.lock().unwrap();
+    let Some(preset) = Preset::by_name(&db, &name) else {
+        return Err(StatusCode::NOT_FOUND.into_response());
+    };
+    let output = conf.lock().unwrap().output.id();
+    if preset.select(&db, &output).is_err() {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
     }
+    ply.set_eq(preset.eq.clone())
+        .map_err(IntoResponse::into_response)?;
+    Ok(Json(preset.eq))
 }
@@ -0,0 +1,764 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    path::Path as FilePath,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    body::{boxed, Body},
+    extract::{Path, Query},
+    http::{
+        header::{CONTENT_TYPE, RANGE},
+        HeaderMap, HeaderValue, Request, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Json, Router,
+};
+use serde_json::{json, Map, Value};
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+
+use crate::{
+    database::{Album, Artist, Cover, Playlist, Song, DB},
+    player::{self, Player},
+};
+
+use super::Config;
+
+const VERSION: &str = "1.16.1";
+
+const ERR_MISSING: u32 = 10;
+const ERR_AUTH: u32 = 40;
+const ERR_NOT_FOUND: u32 = 70;
+
+pub fn subsonic() -> Router {
+    Router::new().route("/:method", get(rest).post(rest))
+}
+
+/// A Subsonic error, still answered with 200 as clients expect.
+struct Failure {
+    code: u32,
+    message: String,
+}
+
+fn failure(code: u32, message: impl Into<String>) -> Failure {
+    Failure {
+        code,
+        message: message.into(),
+    }
+}
+
+impl From<player::Error> for Failure {
+    fn from(err: player::Error) -> Self {
+        failure(0, err.to_string())
+    }
+}
+
+enum Reply {
+    /// merged into the subsonic-response element
+    Data(Value),
+    File(String),
+    Cover(Vec<u8>),
+}
+
+/// Query parameters, keys such as `id` may repeat.
+struct Params(Vec<(String, String)>);
+
+impl Params {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+    fn all(&self, key: &str) -> Vec<&str> {
+        self.0
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+    fn required(&self, key: &str) -> Result<&str, Failure> {
+        self.get(key)
+            .ok_or_else(|| failure(ERR_MISSING, format!("Required parameter is missing: {key}")))
+    }
+    fn parse<T: FromStr>(&self, key: &str) -> Result<T, Failure> {
+        let value = self.required(key)?;
+        value
+            .parse()
+            .map_err(|_| failure(ERR_MISSING, format!("Invalid value for {key}: {value}")))
+    }
+    fn or<T: FromStr>(&self, key: &str, default: T) -> T {
+        self.get(key)
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(default)
+    }
+    fn ids(&self, key: &str) -> Result<Vec<u32>, Failure> {
+        self.all(key)
+            .into_iter()
+            .map(|id| {
+                id.parse()
+                    .map_err(|_| failure(ERR_NOT_FOUND, format!("Not found: {id}")))
+            })
+            .collect()
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/rest/{method}",
+    responses(
+        (status = 200, description = "subsonic-response as XML, or as JSON with f=json, stream, download and getCoverArt answer with the file"),
+    ),
+    params(
+        ("method" = String, Path, description = "Subsonic method, the .view suffix is optional")
+    )
+)]
+pub async fn rest(
+    Extension(db): Extension<Arc<Mutex<DB>>>,
+    Extension(ply): Extension<Player>,
+    Extension(conf): Extension<Arc<Mutex<Config>>>,
+    Path(method): Path<String>,
+    Query(params): Query<Vec<(String, String)>>,
+    headers: HeaderMap,
+) -> Response {
+    let params = Params(params);
+    let json = params.get("f") == Some("json");
+    let auth = authenticate(&params, &conf.lock().unwrap());
+    let reply = match auth {
+        Ok(()) => call(method.trim_end_matches(".view"), &params, &db, &ply, &conf).await,
+        Err(failure) => Err(failure),
+    };
+    match reply {
+        Ok(Reply::Data(data)) => render(json, Ok(data)),
+        Ok(Reply::File(file)) => {
+            // streamed from disk, passing on Range so clients can seek without downloading everything
+            let mut request = Request::new(Body::empty());
+            if let Some(range) = headers.get(RANGE) {
+                request.headers_mut().insert(RANGE, range.clone());
+            }
+            match ServeFile::new(&file).oneshot(request).await {
+                Ok(response) if response.status() != StatusCode::NOT_FOUND => {
+                    let mut response = response.map(boxed);
+                    response
+                        .headers_mut()
+                        .insert(CONTENT_TYPE, HeaderValue::from_static(content_type(&file)));
+                    response
+                }
+                _ => render(json, Err(failure(ERR_NOT_FOUND, "File not found"))),
+            }
+        }
+        Ok(Reply::Cover(cover)) => Response::builder()
+            .header("Content-Type", "image/jpeg")
+            .status(StatusCode::OK)
+            .body(Body::from(cover))
+            .unwrap()
+            .into_response(),
+        Err(failure) => render(json, Err(failure)),
+    }
+}
+
+/// Accepts the salted token `t = md5(password + s)` as well as the plain or hex encoded password `p`.
+fn authenticate(params: &Params, conf: &Config) -> Result<(), Failure> {
+    let Some(account) = &conf.subsonic else {
+        return Err(failure(ERR_AUTH, "The Subsonic API is not enabled"));
+    };
+    let user = params.required("u")?;
+    let valid = match (params.get("t"), params.get("s"), params.get("p")) {
+        (Some(token), Some(salt), _) => {
+            let hash = md5::compute(format!("{}{salt}", account.password));
+            format!("{hash:x}") == token.to_lowercase()
+        }
+        (_, _, Some(password)) => match password.strip_prefix("enc:") {
+            Some(hex) => decode(hex).as_deref() == Some(account.password.as_str()),
+            None => password == account.password,
+        },
+        _ => return Err(failure(ERR_MISSING, "Required parameter is missing: t")),
+    };
+    match valid && user == account.user {
+        true => Ok(()),
+        false => Err(failure(ERR_AUTH, "Wrong username or password")),
+    }
+}
+
+fn decode(hex: &str) -> Option<String> {
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+async fn call(
+    method: &str,
+    params: &Params,
+    db: &Mutex<DB>,
+    ply: &Player,
+    conf: &Mutex<Config>,
+) -> Result<Reply, Failure> {
+    let data = match method {
+        "ping" => json!({}),
+        "getLicense" => json!({ "license": { "valid": true } }),
+        "getMusicFolders" => {
+            let folders: Vec<Value> = conf
+                .lock()
+                .unwrap()
+                .music
+                .iter()
+                .enumerate()
+                .map(|(id, path)| json!({ "id": id, "name": path }))
+                .collect();
+            json!({ "musicFolders": { "musicFolder": folders } })
+        }
+        "getArtists" | "getIndexes" => {
+            let mut index: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+            for artist in Artist::all(&db.lock().unwrap()) {
+                let letter = match artist.name.chars().next() {
+                    Some(c) if c.is_alphabetic() => c.to_uppercase().to_string(),
+                    _ => "#".into(),
+                };
+                index.entry(letter).or_default().push(artist_json(&artist));
+            }
+            let index: Vec<Value> = index
+                .into_iter()
+                .map(|(name, artists)| json!({ "name": name, "artist": artists }))
+                .collect();
+            match method {
+                "getArtists" => json!({ "artists": { "ignoredArticles": "", "index": index } }),
+                _ => {
+                    json!({ "indexes": { "ignoredArticles": "", "lastModified": 0, "index": index } })
+                }
+            }
+        }
+        "getArtist" => {
+            let db = db.lock().unwrap();
+            let artist = Artist::by_id(&db, params.parse("id")?)
+                .ok_or_else(|| failure(ERR_NOT_FOUND, "Artist not found"))?;
+            let albums: Vec<Value> = Album::by_artist_id(&db, artist.artist_id)
+                .iter()
+                .map(|album| album_json(&db, album))
+                .collect();
+            let mut artist = artist_json(&artist);
+            artist["album"] = albums.into();
+            json!({ "artist": artist })
+        }
+        "getAlbum" => {
+            let db = db.lock().unwrap();
+            let album = Album::by_id(&db, params.parse("id")?)
+                .ok_or_else(|| failure(ERR_NOT_FOUND, "Album not found"))?;
+            let songs: Vec<Value> = Song::by_album_id(&db, album.album_id)
+                .iter()
+                .map(song_json)
+                .collect();
+            let mut album = album_json(&db, &album);
+            album["song"] = songs.into();
+            json!({ "album": album })
+        }
+        "getSong" => {
+            let song = Song::by_id(&db.lock().unwrap(), params.parse("id")?)
+                .ok_or_else(|| failure(ERR_NOT_FOUND, "Song not found"))?;
+            json!({ "song": song_json(&song) })
+        }
+        "search3" => {
+            // some clients send "" to list everything
+            let query = params.required("query")?.trim_matches('"');
+            let page = |len: usize, kind: &str| {
+                let offset = params.or(&format!("{kind}Offset"), 0);
+                let count = params.or(&format!("{kind}Count"), 20);
+                offset.min(len)..offset.saturating_add(count).min(len)
+            };
+            let db = db.lock().unwrap();
+            let artists = Artist::by_name(&db, query);
+            let albums = Album::by_title(&db, query);
+            let songs = Song::by_title(&db, query);
+            let artists: Vec<Value> = artists[page(artists.len(), "artist")]
+                .iter()
+                .map(artist_json)
+                .collect();
+            let albums: Vec<Value> = albums[page(albums.len(), "album")]
+                .iter()
+                .map(|album| album_json(&db, album))
+                .collect();
+            let songs: Vec<Value> = songs[page(songs.len(), "song")]
+                .iter()
+                .map(song_json)
+                .collect();
+            json!({ "searchResult3": { "artist": artists, "album": albums, "song": songs } })
+        }
+        "getCoverArt" => {
+            return Cover::by_album_id(&db.lock().unwrap(), params.parse("id")?)
+                .map(Reply::Cover)
+                .ok_or_else(|| failure(ERR_NOT_FOUND, "Cover art not found"));
+        }
+        // files are sent as they are, transcoding is not supported
+        "stream" | "download" => {
+            return Song::by_id(&db.lock().unwrap(), params.parse("id")?)
+                .map(|song| Reply::File(song.file))
+                .ok_or_else(|| failure(ERR_NOT_FOUND, "Song not found"));
+        }
+        // play counts are not kept, scrobbles are accepted and dropped
+        "scrobble" => {
+            params.ids("id")?;
+            json!({})
+        }
+        "getPlaylists" => {
+            let db = db.lock().unwrap();
+            let user = &params.required("u")?;
+            let playlists: Vec<Value> = Playlist::all(&db)
+                .iter()
+                .map(|playlist| playlist_json(&db, playlist, user))
+                .collect();
+            json!({ "playlists": { "playlist": playlists } })
+        }
+        "getPlaylist" => {
+            let db = db.lock().unwrap();
+            let playlist = Playlist::by_id(&db, params.parse("id")?)
+                .ok_or_else(|| failure(ERR_NOT_FOUND, "Playlist not found"))?;
+            let mut json = playlist_json(&db, &playlist, params.required("u")?);
+            json["entry"] = playlist.songs(&db).iter().map(song_json).collect();
+            json!({ "playlist": json })
+        }
+        "createPlaylist" => {
+            let db = db.lock().unwrap();
+            let songs = params.ids("songId")?;
+            let playlist = match params.get("playlistId") {
+                Some(_) => Playlist::by_id(&db, params.parse("playlistId")?)
+                    .ok_or_else(|| failure(ERR_NOT_FOUND, "Playlist not found"))?,
+                None => Playlist::create(&db, params.required("name")?)
+                    .map_err(|err| failure(0, format!("Could not create playlist: {err}")))?,
+            };
+            playlist
+                .set_entries(&db, &songs)
+                .map_err(|err| failure(0, format!("Could not save playlist: {err}")))?;
+            let playlist = Playlist::by_id(&db, playlist.playlist_id)
+                .ok_or_else(|| failure(ERR_NOT_FOUND, "Playlist not found"))?;
+            let mut json = playlist_json(&db, &playlist, params.required("u")?);
+            json["entry"] = playlist.songs(&db).iter().map(song_json).collect();
+            json!({ "playlist": json })
+        }
+        "updatePlaylist" => {
+            let db = db.lock().unwrap();
+            let playlist = Playlist::by_id(&db, params.parse("playlistId")?)
+                .ok_or_else(|| failure(ERR_NOT_FOUND, "Playlist not found"))?;
+            if let Some(name) = params.get("name") {
+                playlist
+                    .rename(&db, name)
+                    .map_err(|err| failure(0, format!("Could not rename playlist: {err}")))?
+            }
+            let mut remove = params.ids("songIndexToRemove")?;
+            remove.sort_unstable();
+            let save = |err| failure(0, format!("Could not save playlist: {err}"));
+            for pos in remove.into_iter().rev() {
+                playlist.remove(&db, pos as usize).map_err(save)?;
+            }
+            playlist
+                .add(&db, &params.ids("songIdToAdd")?)
+                .map_err(save)?;
+            json!({})
+        }
+        "deletePlaylist" => {
+            let db = db.lock().unwrap();
+            Playlist::by_id(&db, params.parse("id")?)
+                .ok_or_else(|| failure(ERR_NOT_FOUND, "Playlist not found"))?
+                .delete(&db)
+                .map_err(|err| failure(0, format!("Could not delete playlist: {err}")))?;
+            json!({})
+        }
+        "jukeboxControl" => jukebox(params, db, ply, conf).await?,
+        _ => return Err(failure(ERR_NOT_FOUND, format!("Unknown method: {method}"))),
+    };
+    Ok(Reply::Data(data))
+}
+
+/// Drives the local player, its queue is the jukebox playlist.
+async fn jukebox(
+    params: &Params,
+    db: &Mutex<DB>,
+    ply: &Player,
+    conf: &Mutex<Config>,
+) -> Result<Value, Failure> {
+    let action = params.required("action")?;
+    let songs = || -> Result<Vec<Song>, Failure> {
+        let db = db.lock().unwrap();
+        params
+            .ids("id")?
+            .into_iter()
+            .map(|id| {
+                Song::by_id(&db, id)
+                    .ok_or_else(|| failure(ERR_NOT_FOUND, format!("Song not found: {id}")))
+            })
+            .collect()
+    };
+    match action {
+        "get" | "status" => {}
+        "set" => {
+            let songs = songs()?;
+            ply.replace(songs)?
+        }
+        "add" => {
+            let songs = songs()?;
+            ply.insert(usize::MAX, songs)?
+        }
+        "start" => match ply.status().await?.duration.is_zero() {
+            true => ply.play()?,
+            false => ply.set_pause(false)?,
+        },
+        "stop" => ply.set_pause(true)?,
+        "skip" => {
+            let index: usize = params.parse("index")?;
+            let offset: f64 = params.or("offset", 0.);
+            let offset = Duration::try_from_secs_f64(offset)
+                .map_err(|_| failure(ERR_MISSING, format!("Invalid value for offset: {offset}")))?;
+            if index >= ply.queue().await?.songs.len() {
+                return Err(failure(ERR_NOT_FOUND, "Index out of range"));
+            }
+            ply.index(index)?;
+            if !offset.is_zero() {
+                // index queues the actual start, waiting on a reply makes sure the seek comes after it
+                ply.status().await?;
+                ply.set_position(offset)?
+            }
+        }
+        "clear" => ply.clear()?,
+        "remove" => ply.delete(params.parse("index")?)?,
+        // the queue keeps its entries and only shuffles the play order, turning it on again reshuffles
+        "shuffle" => {
+            let mut mode = ply.mode().await?;
+            mode.shuffle = false;
+            ply.set_mode(mode)?;
+            mode.shuffle = true;
+            ply.set_mode(mode)?;
+            let mut conf = conf.lock().unwrap();
+            conf.mode = mode;
+            conf.write()
+                .map_err(|err| failure(0, format!("Could not save the config: {err}")))?
+        }
+        "setGain" => {
+            let mut volume = ply.volume().await?;
+            let gain: f32 = params.parse("gain")?;
+            if !gain.is_finite() {
+                return Err(failure(
+                    ERR_MISSING,
+                    format!("Invalid value for gain: {gain}"),
+                ));
+            }
+            volume.level = gain;
+            let volume = volume.clamped();
+            ply.set_volume(volume)?;
+            let mut conf = conf.lock().unwrap();
+            conf.volume = volume;
+            conf.write()
+                .map_err(|err| failure(0, format!("Could not save the config: {err}")))?
+        }
+        _ => return Err(failure(0, format!("Unknown jukebox action: {action}"))),
+    }
+    let now = ply.status().await?;
+    let mut status = json!({
+        "currentIndex": match now.song {
+            Some(_) => now.index as i64,
+            None => -1,
+        },
+        "playing": now.song.is_some() && !now.duration.is_zero() && !now.paused,
+        "gain": now.volume.level,
+        "position": now.position.as_secs(),
+    });
+    Ok(match action {
+        "get" => {
+            let queue = ply.queue().await?;
+            status["entry"] = queue.songs.iter().map(song_json).collect();
+            json!({ "jukeboxPlaylist": status })
+        }
+        _ => json!({ "jukeboxStatus": status }),
+    })
+}
+
+fn artist_json(artist: &Artist) -> Value {
+    json!({
+        "id": artist.artist_id.to_string(),
+        "name": artist.name,
+        "albumCount": artist.albums,
+    })
+}
+
+fn album_json(db: &DB, album: &Album) -> Value {
+    let ms: u32 = Song::by_album_id(db, album.album_id)
+        .iter()
+        .map(|song| song.ms)
+        .sum();
+    let mut json = json!({
+        "id": album.album_id.to_string(),
+        "name": album.title,
+        "artist": album.artist,
+        "artistId": album.artist_id.to_string(),
+        "coverArt": album.album_id.to_string(),
+        "songCount": album.songs,
+        "duration": ms / 1000,
+    });
+    if album.year > 0 {
+        json["year"] = album.year.into()
+    }
+    json
+}
+
+fn song_json(song: &Song) -> Value {
+    let suffix = FilePath::new(&song.file)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    json!({
+        "id": song.song_id.to_string(),
+        "parent": song.album_id.to_string(),
+        "isDir": false,
+        "title": song.title,
+        "album": song.album,
+        "artist": song.artist,
+        "albumId": song.album_id.to_string(),
+        "artistId": song.artist_id.to_string(),
+        "coverArt": song.album_id.to_string(),
+        "duration": song.ms / 1000,
+        "size": song.size,
+        "suffix": suffix,
+        "contentType": content_type(&song.file),
+        "type": "music",
+        "isVideo": false,
+    })
+}
+
+fn playlist_json(db: &DB, playlist: &Playlist, owner: &str) -> Value {
+    let ms: u32 = playlist.songs(db).iter().map(|song| song.ms).sum();
+    json!({
+        "id": playlist.playlist_id.to_string(),
+        "name": playlist.name,
+        "owner": owner,
+        "public": false,
+        "songCount": playlist.songs,
+        "duration": ms / 1000,
+    })
+}
+
+fn content_type(file: &str) -> &'static str {
+    let ext = FilePath::new(file)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default();
+    match ext.to_lowercase().as_str() {
+        "mp3" => "audio/mpeg",
+        "flac" => "audio/flac",
+        "ogg" => "audio/ogg",
+        "m4a" => "audio/mp4",
+        "wav" => "audio/wav",
+        _ => "application/octet-stream",
+    }
+}
+
+fn render(json: bool, data: Result<Value, Failure>) -> Response {
+    let mut body = Map::new();
+    body.insert("version".into(), VERSION.into());
+    body.insert("type".into(), "yampd".into());
+    body.insert("serverVersion".into(), env!("CARGO_PKG_VERSION").into());
+    body.insert("openSubsonic".into(), true.into());
+    let data = match data {
+        Ok(Value::Object(data)) => Ok(data),
+        Ok(_) => Err(failure(0, "The response is not an object")),
+        Err(failure) => Err(failure),
+    };
+    match data {
+        Ok(data) => {
+            body.insert("status".into(), "ok".into());
+            body.extend(data)
+        }
+        Err(failure) => {
+            body.insert("status".into(), "failed".into());
+            body.insert(
+                "error".into(),
+                json!({ "code": failure.code, "message": failure.message }),
+            );
+        }
+    }
+    if json {
+        return Json(json!({ "subsonic-response": body })).into_response();
+    }
+    body.insert("xmlns".into(), "http://subsonic.org/restapi".into());
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+    xml("subsonic-response", &Value::Object(body), &mut out);
+    Response::builder()
+        .header("Content-Type", "text/xml; charset=utf-8")
+        .status(StatusCode::OK)
+        .body(Body::from(out))
+        .unwrap()
+        .into_response()
+}
+
+/// Writes `value` the way Subsonic lays out XML, scalars become attributes and lists repeat the element.
+fn xml(name: &str, value: &Value, out: &mut String) {
+    let text = |value: &Value| match value {
+        Value::String(s) => quick_xml::escape::escape(s).into_owned(),
+        value => value.to_string(),
+    };
+    match value {
+        Value::Array(items) => items.iter().for_each(|item| xml(name, item, out)),
+        Value::Object(map) => {
+            write!(out, "<{name}").unwrap();
+            for (key, value) in map {
+                if !value.is_object() && !value.is_array() {
+                    write!(out, " {key}=\"{}\"", text(value)).unwrap();
+                }
+            }
+            out.push('>');
+            for (key, value) in map {
+                if value.is_object() || value.is_array() {
+                    xml(key, value, out)
+                }
+            }
+            write!(out, "</{name}>").unwrap();
+        }
+        value => write!(out, "<{name}>{}</{name}>", text(value)).unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use axum::{body::HttpBody, response::Response};
+    use serde_json::{json, Value};
+
+    use super::{authenticate, call, render, Params, Reply, ERR_AUTH, ERR_MISSING, ERR_NOT_FOUND};
+    use crate::{
+        database::DB,
+        player::{bus, Player},
+        server::Config,
+    };
+
+    fn conf() -> Config {
+        serde_json::from_value(json!({
+            "db_path": ":memory:",
+            "music": [],
+            "addr": "127.0.0.1:0",
+            "output": { "type": "null" },
+            "subsonic": { "user": "u", "password": "sesame" },
+        }))
+        .unwrap()
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> Params {
+        Params(
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        )
+    }
+
+    async fn text(response: Response) -> String {
+        let mut body = response.into_body();
+        let mut out = vec![];
+        while let Some(chunk) = body.data().await {
+            out.extend_from_slice(&chunk.unwrap())
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn authenticates_with_token_or_password() {
+        let conf = conf();
+        let auth =
+            |pairs: &[(&str, &str)]| authenticate(&params(pairs), &conf).err().map(|f| f.code);
+        let token = format!("{:x}", md5::compute("sesamesalt"));
+        assert_eq!(auth(&[("u", "u"), ("t", &token), ("s", "salt")]), None);
+        let upper = token.to_uppercase();
+        assert_eq!(auth(&[("u", "u"), ("t", &upper), ("s", "salt")]), None);
+        assert_eq!(
+            auth(&[("u", "u"), ("t", &token), ("s", "pepper")]),
+            Some(ERR_AUTH)
+        );
+        assert_eq!(auth(&[("u", "u"), ("p", "sesame")]), None);
+        assert_eq!(auth(&[("u", "u"), ("p", "enc:736573616d65")]), None);
+        assert_eq!(
+            auth(&[("u", "u"), ("p", "enc:736573616d6")]),
+            Some(ERR_AUTH)
+        );
+        assert_eq!(auth(&[("u", "u"), ("p", "open")]), Some(ERR_AUTH));
+        assert_eq!(auth(&[("u", "v"), ("p", "sesame")]), Some(ERR_AUTH));
+        assert_eq!(auth(&[("u", "u")]), Some(ERR_MISSING));
+        assert_eq!(auth(&[("p", "sesame")]), Some(ERR_MISSING));
+
+        let mut off = conf.clone();
+        off.subsonic = None;
+        let failed = authenticate(&params(&[("u", "u"), ("p", "sesame")]), &off);
+        assert_eq!(failed.err().map(|f| f.code), Some(ERR_AUTH));
+    }
+
+    #[test]
+    fn parses_params() {
+        let params = params(&[("id", "1"), ("id", "2"), ("count", "x"), ("name", "Mix")]);
+        assert_eq!(params.get("id"), Some("1"));
+        assert_eq!(params.all("id"), ["1", "2"]);
+        assert_eq!(params.ids("id").ok(), Some(vec![1, 2]));
+        assert_eq!(params.ids("songId").ok(), Some(vec![]));
+        assert_eq!(
+            params.ids("name").err().map(|f| f.code),
+            Some(ERR_NOT_FOUND)
+        );
+        assert_eq!(params.required("name").ok(), Some("Mix"));
+        let missing = params.required("query").err().unwrap();
+        assert_eq!(missing.code, ERR_MISSING);
+        assert_eq!(missing.message, "Required parameter is missing: query");
+        let invalid = params.parse::<u32>("count").err().unwrap();
+        assert_eq!(invalid.code, ERR_MISSING);
+        assert_eq!(invalid.message, "Invalid value for count: x");
+        assert_eq!(params.or("count", 20), 20);
+        assert_eq!(params.or("offset", 5), 5);
+        assert_eq!(params.or("id", 0), 1);
+    }
+
+    #[tokio::test]
+    async fn renders_json_and_xml() {
+        let data = || Ok(json!({ "song": [{ "id": "1", "title": "A & B" }, { "id": "2" }] }));
+        let body: Value = serde_json::from_str(&text(render(true, data())).await).unwrap();
+        let body = &body["subsonic-response"];
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["version"], "1.16.1");
+        assert_eq!(body["song"][0]["title"], "A & B");
+
+        let body = text(render(false, data())).await;
+        assert!(body.starts_with(r#"<?xml version="1.0" encoding="UTF-8"?><subsonic-response "#));
+        assert!(body.contains(r#" status="ok""#));
+        assert!(body.contains(r#" xmlns="http://subsonic.org/restapi""#));
+        assert!(body.contains(
+            r#"><song id="1" title="A &amp; B"></song><song id="2"></song></subsonic-response>"#
+        ));
+
+        let body = text(render(
+            false,
+            Err(super::failure(ERR_NOT_FOUND, "Song not found")),
+        ))
+        .await;
+        assert!(body.contains(r#" status="failed""#));
+        assert!(body.contains(r#"<error code="70" message="Song not found"></error>"#));
+
+        // anything but an object is a failure rather than a panic
+        let body: Value = serde_json::from_str(&text(render(true, Ok(json!([1])))).await).unwrap();
+        assert_eq!(body["subsonic-response"]["status"], "failed");
+        assert_eq!(body["subsonic-response"]["error"]["code"], 0);
+    }
+
+    #[tokio::test]
+    async fn search_pages_do_not_overflow() {
+        let conf = conf();
+        let ply = Player::new(&conf, bus());
+        let db = Mutex::new(DB::open(":memory:").unwrap());
+        let max = usize::MAX.to_string();
+        let params = params(&[("query", ""), ("songOffset", "1"), ("songCount", &max)]);
+        let reply = call("search3", &params, &db, &ply, &Mutex::new(conf)).await;
+        let Ok(Reply::Data(data)) = reply else {
+            panic!("search3 failed")
+        };
+        assert_eq!(data["searchResult3"]["song"], json!([]));
+    }
+}
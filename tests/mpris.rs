@@ -0,0 +1,188 @@
+//! Runs yampd against a private session bus and drives it over MPRIS.
+//! Needs `dbus-daemon` on the PATH: `cargo test --features mpris`.
+#![cfg(feature = "mpris")]
+
+use std::{
+    collections::HashMap,
+    env,
+    f32::consts::TAU,
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::Path,
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+use serde_json::json;
+use tokio::time::sleep;
+use zbus::{
+    proxy::{Builder, CacheProperties},
+    zvariant::OwnedValue,
+    Proxy,
+};
+
+/// Kills the process when the test ends, passing or not.
+struct Kill(Child);
+
+impl Drop for Kill {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn tone(path: &Path) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let spec = WavSpec {
+        channels: 2,
+        sample_rate: 48_000,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut writer = WavWriter::create(path, spec).unwrap();
+    for i in 0..48_000 * 5 {
+        let sample = ((i as f32 * 440. / 48_000. * TAU).sin() * 16_000.) as i16;
+        writer.write_sample(sample).unwrap();
+        writer.write_sample(sample).unwrap();
+    }
+    writer.finalize().unwrap()
+}
+
+/// Sends a bodyless POST and returns the status line.
+fn post(addr: &str, path: &str) -> String {
+    let Ok(mut stream) = TcpStream::connect(addr) else {
+        return String::new();
+    };
+    write!(
+        stream,
+        "POST {path} HTTP/1.1\r\nHost: {addr}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+    )
+    .unwrap();
+    let mut response = String::new();
+    let _ = stream.read_to_string(&mut response);
+    response.lines().next().unwrap_or_default().into()
+}
+
+async fn status(player: &Proxy<'_>) -> String {
+    player.get_property("PlaybackStatus").await.unwrap()
+}
+
+#[tokio::test]
+async fn play_pause_and_metadata() {
+    let dir = env::temp_dir().join(format!("yampd-mpris-{}", std::process::id()));
+    tone(&dir.join("music/Artist/Album/01 - Tone.wav"));
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let addr = format!("127.0.0.1:{port}");
+    fs::create_dir_all(dir.join("config")).unwrap();
+    fs::create_dir_all(dir.join("cache")).unwrap();
+    let conf = json!({
+        "db_path": dir.join("cache/yampd.db"),
+        "music": [dir.join("music")],
+        "addr": addr,
+        "output": { "type": "null" },
+    });
+    fs::write(dir.join("config/yampd.json"), conf.to_string()).unwrap();
+
+    let mut daemon = Kill(
+        Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("dbus-daemon is needed for this test"),
+    );
+    let mut bus = String::new();
+    BufReader::new(daemon.0.stdout.as_mut().unwrap())
+        .read_line(&mut bus)
+        .unwrap();
+    let bus = bus.trim().to_owned();
+    let yampd = Kill(
+        Command::new(env!("CARGO_BIN_EXE_yampd"))
+            .env("DBUS_SESSION_BUS_ADDRESS", &bus)
+            .env("XDG_CONFIG_HOME", dir.join("config"))
+            .env("XDG_CACHE_HOME", dir.join("cache"))
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+
+    // the song shows up once the server listens and the scan is done
+    let mut queued = false;
+    for _ in 0..100 {
+        if post(&addr, "/ply/queue/song/1").contains("200") {
+            queued = true;
+            break;
+        }
+        sleep(Duration::from_millis(100)).await
+    }
+    assert!(queued, "yampd did not come up");
+
+    let conn = zbus::connection::Builder::address(bus.as_str())
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    let player: Proxy = Builder::new(&conn)
+        .destination("org.mpris.MediaPlayer2.yampd")
+        .unwrap()
+        .path("/org/mpris/MediaPlayer2")
+        .unwrap()
+        .interface("org.mpris.MediaPlayer2.Player")
+        .unwrap()
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await
+        .unwrap();
+    let mut registered = false;
+    for _ in 0..50 {
+        if player
+            .get_property::<String>("PlaybackStatus")
+            .await
+            .is_ok()
+        {
+            registered = true;
+            break;
+        }
+        sleep(Duration::from_millis(100)).await
+    }
+    assert!(registered, "yampd did not register on the bus");
+    assert_eq!(status(&player).await, "Stopped");
+
+    player.call_method("PlayPause", &()).await.unwrap();
+    sleep(Duration::from_millis(300)).await;
+    assert_eq!(status(&player).await, "Playing");
+    let meta: HashMap<String, OwnedValue> = player.get_property("Metadata").await.unwrap();
+    let title: String = meta["xesam:title"].try_clone().unwrap().try_into().unwrap();
+    assert_eq!(title, "Tone");
+    let length: i64 = meta["mpris:length"]
+        .try_clone()
+        .unwrap()
+        .try_into()
+        .unwrap();
+    assert_eq!(length, 5_000_000);
+
+    player.call_method("PlayPause", &()).await.unwrap();
+    sleep(Duration::from_millis(300)).await;
+    assert_eq!(status(&player).await, "Paused");
+    player.call_method("Stop", &()).await.unwrap();
+    sleep(Duration::from_millis(300)).await;
+    assert_eq!(status(&player).await, "Stopped");
+
+    // the mode is stored like one set over HTTP or MPD, a volume that is not a number is refused
+    player.set_property("Shuffle", true).await.unwrap();
+    let conf = fs::read_to_string(dir.join("config/yampd.json")).unwrap();
+    let conf: serde_json::Value = serde_json::from_str(&conf).unwrap();
+    assert_eq!(conf["mode"]["shuffle"], true);
+    assert!(player.set_property("Volume", f64::NAN).await.is_err());
+    let volume: f64 = player.get_property("Volume").await.unwrap();
+    assert_eq!(volume, 1.);
+
+    drop(yampd);
+    fs::remove_dir_all(dir).unwrap();
+}